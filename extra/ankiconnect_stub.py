@@ -0,0 +1,105 @@
+#!/usr/bin/env python3
+# A stand-in for the AnkiConnect add-on, to try `oraki sync` without running Anki.
+# It keeps everything in memory and implements only the actions oraki uses.
+#
+#   python3 extra/ankiconnect_stub.py 8766 &
+#   oraki sync --url http://127.0.0.1:8766
+
+import json
+import re
+import sys
+from http.server import BaseHTTPRequestHandler, HTTPServer
+
+models = {}
+decks = {"Default"}
+notes = {}
+media = {}
+
+
+def find_notes(query):
+    model = re.search(r'"note:([^"]*)"', query)
+    search_result = re.search(r'"search_result:((?:[^"\\]|\\.)*)"', query)
+    search_result = re.sub(r"\\(.)", r"\1", search_result.group(1)) if search_result else None
+    return [
+        note_id
+        for note_id, note in notes.items()
+        if (model is None or note["modelName"] == model.group(1))
+        and (search_result is None or note["fields"].get("search_result") == search_result)
+    ]
+
+
+def notes_info(note_ids):
+    return [
+        {
+            "noteId": note_id,
+            "modelName": notes[note_id]["modelName"],
+            "tags": notes[note_id]["tags"],
+            "fields": {
+                name: {"value": value, "order": order}
+                for order, (name, value) in enumerate(notes[note_id]["fields"].items())
+            },
+        }
+        for note_id in note_ids
+    ]
+
+
+def add_note(note):
+    if note["modelName"] not in models:
+        raise ValueError("model was not found: " + note["modelName"])
+    if note["deckName"] not in decks:
+        raise ValueError("deck was not found: " + note["deckName"])
+    note_id = len(notes) + 1
+    notes[note_id] = {
+        "modelName": note["modelName"],
+        "deckName": note["deckName"],
+        "fields": dict(note["fields"]),
+        "tags": list(note.get("tags", [])),
+    }
+    return note_id
+
+
+def add_tags(note_ids, tags):
+    for note_id in note_ids:
+        for tag in tags.split():
+            if tag not in notes[note_id]["tags"]:
+                notes[note_id]["tags"].append(tag)
+
+
+ACTIONS = {
+    "version": lambda p: 6,
+    "modelNames": lambda p: list(models),
+    "createModel": lambda p: models.setdefault(p["modelName"], p) and None,
+    "deckNames": lambda p: sorted(decks),
+    "updateModelTemplates": lambda p: models[p["model"]["name"]].update(templates=p["model"]["templates"]),
+    "updateModelStyling": lambda p: models[p["model"]["name"]].update(css=p["model"]["css"]),
+    "createDeck": lambda p: decks.add(p["deck"]),
+    "findNotes": lambda p: find_notes(p["query"]),
+    "notesInfo": lambda p: notes_info(p["notes"]),
+    "addNote": lambda p: add_note(p["note"]),
+    "updateNoteFields": lambda p: notes[p["note"]["id"]]["fields"].update(p["note"]["fields"]),
+    "addTags": lambda p: add_tags(p["notes"], p["tags"]),
+    "storeMediaFile": lambda p: media.setdefault(p["filename"], p) and p["filename"],
+}
+
+
+class Handler(BaseHTTPRequestHandler):
+    def do_POST(self):
+        request = json.loads(self.rfile.read(int(self.headers["Content-Length"])))
+        action = ACTIONS.get(request["action"])
+        try:
+            if action is None:
+                raise ValueError("unsupported action")
+            response = {"result": action(request.get("params", {})), "error": None}
+        except Exception as e:
+            response = {"result": None, "error": str(e)}
+        body = json.dumps(response).encode()
+        self.send_response(200)
+        self.send_header("Content-Type", "application/json")
+        self.send_header("Content-Length", str(len(body)))
+        self.end_headers()
+        self.wfile.write(body)
+
+
+if __name__ == "__main__":
+    port = int(sys.argv[1]) if len(sys.argv) > 1 else 8765
+    HTTPServer(("127.0.0.1", port), Handler).serve_forever()
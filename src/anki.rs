@@ -1,80 +1,459 @@
-use crate::utils::{get_main_csv_path, get_main_output_anki_path, get_style_css_path};
-use csv::ReaderBuilder;
-use genanki_rs::{Deck, Error as AnkiError, Field, Model, Note, Template};
+use crate::cloze::make_cloze;
+use crate::config;
+use crate::or::{Sentence, TranslationInfo};
+use crate::storage::Storage;
+use crate::table::escape_html;
+use crate::tags::{part_of_speech, tags};
+use crate::templates::{CardTemplate, TemplateSet};
+use crate::utils::{get_main_output_anki_path, get_media_dir};
+use crate::verb::Verb;
+use clap::ValueEnum;
+use genanki_rs::{Deck, Error as AnkiError, Field, Model, ModelType, Note, Package, Template};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::error::Error;
+use std::path::PathBuf;
 
 const MODEL_ID: i64 = 4198389758;
-const DECK_ID: i64 = 8129319832;
-const DECK_NAME: &str = "Oraki searched words with phrases";
+pub const MODEL_NAME: &str = "Searched russian word model";
+const CLOZE_MODEL_ID: i64 = 4198389759;
+pub const DECK_ID: i64 = 8129319832;
+pub const DECK_NAME: &str = "Oraki searched words with phrases";
 const DECK_DESCRIPTION: &str = "Words searched using oraki with context phrases.";
 const Q_FORMAT: &str = r#"<p class="search_result">{{search_result}}</p><p class="contextPhraseTranslation">{{context_phrase}}</p>"#;
-const A_FORMAT: &str = r#"{{FrontSide}}<hr><p class="mainTranslation">{{main_translation}}</p><span class="contextPhraseTranslation"><p class="otherTranslations">{{other_translations}}</p>{{context_phrase_translation}}</span><p>{{title}} ({{search_query}})</p><br>-<br><div class="overview">{{overview}}</div>"#;
+const A_FORMAT: &str = r#"{{FrontSide}}<hr><p class="mainTranslation">{{main_translation}}</p><span class="contextPhraseTranslation"><p class="otherTranslations">{{other_translations}}</p>{{context_phrase_translation}}</span><p>{{title}} ({{search_query}}) {{audio}}</p><br>-<br><div class="overview">{{overview}}</div><div class="declension">{{declension}}</div><div class="verb">{{verb_tables}}</div>"#;
+// the fronts of optional cards are wholly inside a section on their `*_front` field, which is
+// left empty when the card type isn't selected, so that anki renders an empty front and
+// generates no card for it
+const RECOGNITION_Q_FORMAT: &str = r#"{{#recognition_front}}<p class="search_result">{{recognition_front}}</p>{{/recognition_front}}"#;
+const RECOGNITION_A_FORMAT: &str = r#"{{FrontSide}}<hr><p class="mainTranslation">{{main_translation}}</p><p class="otherTranslations">{{other_translations}}</p><div class="overview">{{overview}}</div>"#;
+const PRODUCTION_Q_FORMAT: &str = r#"{{#production_front}}<div class="production">{{production_front}}</div>{{type:search_result}}{{/production_front}}"#;
+const PRODUCTION_A_FORMAT: &str = r#"{{FrontSide}}<hr><p class="search_result">{{title}}</p>{{audio}}"#;
+const LISTENING_Q_FORMAT: &str = r#"{{#listening_front}}{{listening_front}}{{/listening_front}}"#;
+const LISTENING_A_FORMAT: &str = r#"{{FrontSide}}<hr><p class="search_result">{{title}}</p><p class="mainTranslation">{{main_translation}}</p>"#;
+
+const CLOZE_Q_FORMAT: &str = r#"{{cloze:text}}<p class="contextPhraseTranslation">{{hint}}</p>"#;
+const CLOZE_A_FORMAT: &str = r#"{{cloze:text}}<p class="contextPhraseTranslation">{{hint}}</p><hr><p class="mainTranslation">{{main_translation}}</p><p>{{title}} ({{search_query}}) {{audio}}</p>"#;
+pub const CLOZE_FIELD_NAMES: [&str; 6] = ["text", "hint", "title", "main_translation", "search_query", "audio"];
+
+// order must match `note_fields`
+pub const FIELD_NAMES: [&str; 29] = [
+    "search_query",
+    "search_result",
+    "title",
+    "main_translation",
+    "other_translations",
+    "overview",
+    "context_phrase",
+    "context_phrase_translation",
+    "declension",
+    "aspect",
+    "aspect_partner",
+    "verb_tables",
+    "conjugation_ya",
+    "conjugation_ty",
+    "conjugation_on",
+    "conjugation_my",
+    "conjugation_vy",
+    "conjugation_oni",
+    "past_m",
+    "past_f",
+    "past_n",
+    "past_pl",
+    "imperative_sg",
+    "imperative_pl",
+    "sentences",
+    "audio",
+    "recognition_front",
+    "production_front",
+    "listening_front",
+];
+
+// extra cards besides the default one, the model always has every template so that
+// choosing other card types doesn't change it
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CardType {
+    /// Stressed russian -> meaning.
+    Recognition,
+    /// Meaning -> type the russian.
+    Production,
+    /// Audio -> word and meaning, only for words with audio.
+    Listening,
+}
+
+impl CardType {
+    const ALL: [CardType; 3] = [
+        CardType::Recognition,
+        CardType::Production,
+        CardType::Listening,
+    ];
+
+    // name, front and back
+    fn template(&self) -> (&'static str, &'static str, &'static str) {
+        match self {
+            CardType::Recognition => ("Recognition", RECOGNITION_Q_FORMAT, RECOGNITION_A_FORMAT),
+            CardType::Production => ("Production", PRODUCTION_Q_FORMAT, PRODUCTION_A_FORMAT),
+            CardType::Listening => ("Listening", LISTENING_Q_FORMAT, LISTENING_A_FORMAT),
+        }
+    }
+
+    fn front(&self, translation_info: &TranslationInfo, audio: &str) -> String {
+        match self {
+            CardType::Recognition => escape_html(&translation_info.title),
+            CardType::Production => format!(
+                "<p class=\"mainTranslation\">{}</p><p class=\"otherTranslations\">{}</p>",
+                escape_html(&translation_info.main_translation),
+                escape_html(&translation_info.other_translations_joined())
+            ),
+            CardType::Listening => audio.to_string(),
+        }
+    }
+}
+
+// which example sentence goes into the context phrase fields
+#[derive(Clone, Copy, ValueEnum)]
+pub enum SentenceChoice {
+    First,
+    Random,
+    Shortest,
+    All,
+}
+
+// how notes are split into sub-decks of the main deck, notes that fit none stay in it
+#[derive(Clone, Copy, ValueEnum)]
+pub enum SubDecks {
+    PartOfSpeech,
+    Source,
+}
+
+impl SubDecks {
+    fn sub_deck_name(&self, translation_info: &TranslationInfo) -> Option<String> {
+        match self {
+            SubDecks::PartOfSpeech => part_of_speech(translation_info).map(|p| {
+                let mut chars = p.chars();
+                let first = chars.next().unwrap().to_uppercase();
+                format!("{first}{}s", chars.as_str())
+            }),
+            SubDecks::Source => translation_info.source.clone(),
+        }
+    }
+}
+
+fn chosen_sentences(translation_info: &TranslationInfo, choice: SentenceChoice) -> Vec<Sentence> {
+    // entries saved before all sentences were kept only have the context phrase
+    let sentences = match (&translation_info.context_phrase, translation_info.sentences.is_empty()) {
+        (Some(ru), true) => vec![Sentence {
+            ru: ru.clone(),
+            en: translation_info
+                .context_phrase_translation
+                .clone()
+                .unwrap_or_default(),
+        }],
+        _ => translation_info.sentences.clone(),
+    };
+    match choice {
+        SentenceChoice::First => sentences.into_iter().take(1).collect(),
+        // picked by the guid, so the card keeps the same sentence from one compile to the next
+        SentenceChoice::Random if !sentences.is_empty() => {
+            let i = stable_hash(&translation_info.guid) as usize % sentences.len();
+            vec![sentences[i].clone()]
+        }
+        SentenceChoice::Random => vec![],
+        SentenceChoice::Shortest => sentences
+            .into_iter()
+            .min_by_key(|s| s.ru.chars().count())
+            .into_iter()
+            .collect(),
+        SentenceChoice::All => sentences,
+    }
+}
+
+// the scraped phrase is left out when the user gave their own without keeping both
+fn chosen_context_sentences(
+    translation_info: &TranslationInfo,
+    sentence_choice: SentenceChoice,
+) -> Vec<Sentence> {
+    match translation_info.context_phrase {
+        Some(_) => chosen_sentences(translation_info, sentence_choice),
+        None => vec![],
+    }
+}
+
+fn sentences_html_list(sentences: &[Sentence]) -> String {
+    if sentences.is_empty() {
+        return String::new();
+    }
+    let items = sentences
+        .iter()
+        .map(|s| format!("<li>{}<br>{}</li>", escape_html(&s.ru), escape_html(&s.en)))
+        .collect::<Vec<String>>()
+        .join("");
+    format!("<ul>{items}</ul>")
+}
 
 //const A_FORMAT: &str = r#"{{FrontSide}}<hr><p class="title">{{title}}</p><p>({{search_query}})</p><span class="main_translation">{{main_translation}}</span><span>{{context_phrase_translation}}</span><br>{{other_translations}}<br><div class="overview">{{overview}}</div>"#;
-fn make_anki_model() -> Result<Model, Box<AnkiError>> {
+fn with_custom_css(model: Model, templates: &TemplateSet) -> Model {
+    match &templates.css {
+        Some(css) => model.css(css),
+        None => model,
+    }
+}
+
+// name, front and back of every built-in template in the model
+pub fn model_templates() -> Vec<(&'static str, &'static str, &'static str)> {
+    [("Card 1", Q_FORMAT, A_FORMAT)]
+        .into_iter()
+        .chain(CardType::ALL.iter().map(|c| c.template()))
+        .collect()
+}
+
+pub fn cloze_template() -> (&'static str, &'static str, &'static str) {
+    ("Cloze", CLOZE_Q_FORMAT, CLOZE_A_FORMAT)
+}
+
+fn make_template(card: &CardTemplate) -> Template {
+    Template::new(card.name)
+        .qfmt(&card.q_format)
+        .afmt(&card.a_format)
+}
+
+fn make_anki_model(templates: &TemplateSet) -> Model {
     let model = Model::new(
         MODEL_ID,
-        "Searched russian word model",
-        vec![
-            Field::new("search_query"),
-            Field::new("search_result"),
-            Field::new("title"),
-            Field::new("main_translation"),
-            Field::new("other_translations"),
-            Field::new("overview"),
-            Field::new("context_phrase"),
-            Field::new("context_phrase_translation"),
-        ],
-        vec![Template::new("Card 1").qfmt(Q_FORMAT).afmt(A_FORMAT)],
+        MODEL_NAME,
+        FIELD_NAMES.iter().map(|name| Field::new(name)).collect(),
+        templates.cards.iter().map(make_template).collect(),
     );
-    let custom_css_path = get_style_css_path().unwrap();
-    match custom_css_path {
-        Some(p) => Ok(model.css(std::fs::read_to_string(p).unwrap())),
-        None => {
-            println!("No css found.");
-            Ok(model)
+    with_custom_css(model, templates)
+}
+
+fn make_anki_cloze_model(templates: &TemplateSet) -> Model {
+    let model = Model::new(
+        CLOZE_MODEL_ID,
+        "Searched russian word cloze model",
+        CLOZE_FIELD_NAMES.iter().map(|name| Field::new(name)).collect(),
+        vec![make_template(&templates.cloze)],
+    )
+    .model_type(ModelType::Cloze);
+    with_custom_css(model, templates)
+}
+
+pub fn note_fields(
+    translation_info: &TranslationInfo,
+    sentence_choice: SentenceChoice,
+    card_types: &[CardType],
+) -> Vec<String> {
+    let declension = translation_info
+        .declension
+        .as_ref()
+        .map(|d| d.to_html_table())
+        .unwrap_or_default();
+    let chosen_sentences = chosen_context_sentences(translation_info, sentence_choice);
+    let context_phrases = translation_info
+        .user_context_phrase
+        .iter()
+        .chain(chosen_sentences.iter().map(|s| &s.ru))
+        .map(|s| s.as_str())
+        .collect::<Vec<&str>>();
+    let context_phrase_translations = chosen_sentences
+        .iter()
+        .map(|s| s.en.as_str())
+        .collect::<Vec<&str>>();
+    let audio = audio_field(translation_info);
+    let mut fields = vec![
+        translation_info.search_query.clone(),
+        translation_info.search_result.clone(),
+        translation_info.title.clone(),
+        translation_info.main_translation.clone(),
+        translation_info.other_translations_joined(),
+        translation_info.overview_in_one_line(),
+        context_phrases.join("<br>"),
+        context_phrase_translations.join("<br>"),
+        declension,
+    ];
+    match &translation_info.verb {
+        Some(verb) => {
+            fields.push(verb.aspect.clone().unwrap_or_default());
+            fields.push(verb.aspect_partner.clone().unwrap_or_default());
+            fields.push(verb.to_html_tables());
+            for (forms, n_forms) in [(&verb.conjugation, 6), (&verb.past, 4), (&verb.imperative, 2)] {
+                for n in 0..n_forms {
+                    fields.push(Verb::nth_form(forms, n).to_string());
+                }
+            }
+        }
+        None => fields.resize(FIELD_NAMES.len() - 5, String::new()),
+    }
+    fields.push(sentences_html_list(&translation_info.sentences));
+    fields.push(audio.clone());
+    for card_type in CardType::ALL {
+        fields.push(match card_types.contains(&card_type) {
+            true => card_type.front(translation_info, &audio),
+            false => String::new(),
+        });
+    }
+    fields
+}
+
+// only files that were actually downloaded are referenced
+pub fn audio_media_path(translation_info: &TranslationInfo) -> Option<PathBuf> {
+    let audio_file = translation_info.audio_file.as_ref()?;
+    let file_path = get_media_dir().ok()?.join(audio_file);
+    file_path.is_file().then_some(file_path)
+}
+
+// stable across re-fetches and field edits, so anki updates the note instead of duplicating it
+pub fn note_guid(search_result: &str) -> String {
+    let normalized = search_result
+        .trim()
+        .chars()
+        .filter(|c| *c != '\'' && *c != '\u{301}')
+        .flat_map(|c| c.to_lowercase())
+        .collect::<String>();
+    format!("oraki-{:016x}", stable_hash(&normalized))
+}
+
+// FNV-1a, since the std hasher isn't guaranteed to stay the same between releases
+pub fn stable_hash(s: &str) -> u64 {
+    s.bytes().fold(0xcbf29ce484222325_u64, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+pub fn deck_id_for(name: &str) -> i64 {
+    (stable_hash(name) >> 1) as i64
+}
+
+fn audio_field(translation_info: &TranslationInfo) -> String {
+    match audio_media_path(translation_info) {
+        Some(p) => format!("[sound:{}]", p.file_name().unwrap().to_string_lossy()),
+        None => String::new(),
+    }
+}
+
+// None when the word can't be found in any of its context phrases
+fn create_cloze_note_from_translation_info(
+    model: Model,
+    translation_info: &TranslationInfo,
+    sentence_choice: SentenceChoice,
+) -> Result<Option<Note>, Box<AnkiError>> {
+    // the user's phrase has no translation, the word's own is the hint instead
+    let user_phrase = translation_info.user_context_phrase.iter().map(|ru| Sentence {
+        ru: ru.clone(),
+        en: translation_info.main_translation.clone(),
+    });
+    let sentences = user_phrase.chain(chosen_context_sentences(translation_info, sentence_choice));
+    for sentence in sentences {
+        if let Some(text) = make_cloze(&sentence.ru, translation_info) {
+            let audio = audio_field(translation_info);
+            let tags = tags(translation_info);
+            let note = Note::new_with_options(
+                model,
+                vec![
+                    text.as_str(),
+                    sentence.en.as_str(),
+                    translation_info.title.as_str(),
+                    translation_info.main_translation.as_str(),
+                    translation_info.search_query.as_str(),
+                    audio.as_str(),
+                ],
+                None,
+                Some(tags.iter().map(|t| t.as_str()).collect()),
+                Some(&format!("{}-cloze", translation_info.guid)),
+            )?;
+            return Ok(Some(note));
         }
     }
+    Ok(None)
 }
 
-fn create_note_from_result(
+fn create_note_from_translation_info(
     model: Model,
-    result: csv::StringRecord,
+    translation_info: &TranslationInfo,
+    sentence_choice: SentenceChoice,
+    card_types: &[CardType],
 ) -> Result<Note, Box<AnkiError>> {
-    Ok(Note::new(
+    let fields = note_fields(translation_info, sentence_choice, card_types);
+    let tags = tags(translation_info);
+    let note = Note::new_with_options(
         model,
-        vec![
-            result.get(0).unwrap(),
-            result.get(1).unwrap(),
-            result.get(2).unwrap(),
-            result.get(3).unwrap(),
-            result.get(4).unwrap(),
-            result.get(5).unwrap(),
-            result.get(6).unwrap(),
-            result.get(7).unwrap(),
-        ],
-    )
-    .unwrap_or_else(|_| panic!("Could not create note from {}", result.as_slice())))
+        fields.iter().map(|f| f.as_str()).collect(),
+        None,
+        Some(tags.iter().map(|t| t.as_str()).collect()),
+        Some(&translation_info.guid),
+    )?;
+    Ok(note)
+}
+
+pub fn deck_name(translation_info: &TranslationInfo, sub_decks: Option<SubDecks>) -> String {
+    match sub_decks.and_then(|s| s.sub_deck_name(translation_info)) {
+        Some(sub_deck_name) => format!("{}::{sub_deck_name}", config::get().deck_name),
+        None => config::get().deck_name.clone(),
+    }
 }
 
-pub fn create_deck_from_csv() -> Result<(), Box<dyn Error>> {
-    let mut reader = ReaderBuilder::new()
-        .delimiter(b'|')
-        .from_path(get_main_csv_path()?)?;
-    let mut my_deck = Deck::new(DECK_ID, DECK_NAME, DECK_DESCRIPTION);
+// with `cloze`, words found in their context phrase become cloze notes instead
+pub fn create_deck_from_storage(
+    sentence_choice: SentenceChoice,
+    card_types: &[CardType],
+    cloze: bool,
+    sub_decks: Option<SubDecks>,
+    templates: &TemplateSet,
+) -> Result<(), Box<dyn Error>> {
+    let storage = Storage::open()?;
+    let model = make_anki_model(templates);
+    let cloze_model = make_anki_cloze_model(templates);
+    let mut decks = BTreeMap::new();
+    let config = config::get();
+    decks.insert(
+        config.deck_name.clone(),
+        Deck::new(config.deck_id, &config.deck_name, DECK_DESCRIPTION),
+    );
     let mut seen_search_results: Vec<String> = vec![];
-    for record in reader.records() {
-        let result = record?;
-        let result_search_result = result[2].to_string();
-        if seen_search_results.contains(&result_search_result) {
-            println!("Skipping note for {} (already exists)...", &result[2]);
+    let mut media_files: Vec<String> = vec![];
+    for translation_info in storage.in_deck()? {
+        if seen_search_results.contains(&translation_info.search_result) {
+            println!("Skipping note for {} (already exists)...", translation_info.title);
             continue;
         }
-        println!("Creating note for {}...", &result[2]);
-        let note = create_note_from_result(make_anki_model()?, result)?;
-        seen_search_results.push(result_search_result);
-        my_deck.add_note(note);
+        let cloze_note = match cloze {
+            true => create_cloze_note_from_translation_info(
+                cloze_model.clone(),
+                &translation_info,
+                sentence_choice,
+            )?,
+            false => None,
+        };
+        let note = match cloze_note {
+            Some(note) => {
+                println!("Creating cloze note for {}...", translation_info.title);
+                note
+            }
+            None => {
+                println!("Creating note for {}...", translation_info.title);
+                create_note_from_translation_info(
+                    model.clone(),
+                    &translation_info,
+                    sentence_choice,
+                    card_types,
+                )?
+            }
+        };
+        if let Some(p) = audio_media_path(&translation_info) {
+            media_files.push(p.to_str().unwrap().to_string());
+        }
+        let deck_name = deck_name(&translation_info, sub_decks);
+        decks
+            .entry(deck_name.clone())
+            .or_insert_with(|| Deck::new(deck_id_for(&deck_name), &deck_name, DECK_DESCRIPTION))
+            .add_note(note);
+        seen_search_results.push(translation_info.search_result);
     }
-    my_deck.write_to_file(get_main_output_anki_path().unwrap().to_str().unwrap())?;
+    let mut package = Package::new(
+        decks.into_values().collect(),
+        media_files.iter().map(|f| f.as_str()).collect(),
+    )?;
+    package.write_to_file(get_main_output_anki_path().unwrap().to_str().unwrap())?;
     Ok(())
 }
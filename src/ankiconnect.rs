@@ -0,0 +1,525 @@
+use crate::anki::{
+    audio_media_path, deck_name, note_fields, CardType, SentenceChoice, SubDecks, FIELD_NAMES,
+    MODEL_NAME,
+};
+use crate::or::TranslationInfo;
+use crate::storage::Storage;
+use crate::tags::tags;
+use crate::templates::TemplateSet;
+use reqwest::header::CONTENT_TYPE;
+use serde_json::{json, Map, Value};
+use std::collections::HashSet;
+use std::error::Error;
+
+pub const DEFAULT_URL: &str = "http://127.0.0.1:8765";
+const VERSION: u8 = 6;
+
+#[derive(Debug, Default)]
+pub struct SyncResult {
+    pub added: Vec<String>,
+    pub updated: Vec<String>,
+    pub unchanged: Vec<String>,
+}
+
+// a client for the AnkiConnect add-on, or anything that speaks its protocol
+pub struct AnkiConnect {
+    client: reqwest::Client,
+    url: String,
+}
+
+impl AnkiConnect {
+    pub fn new(url: &str) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            url: url.to_string(),
+        }
+    }
+
+    async fn request(&self, action: &str, params: Value) -> Result<Value, Box<dyn Error>> {
+        let body = json!({ "action": action, "version": VERSION, "params": params });
+        let response_text = self
+            .client
+            .post(&self.url)
+            .header(CONTENT_TYPE, "application/json")
+            .body(body.to_string())
+            .send()
+            .await
+            .map_err(|e| format!("Could not reach AnkiConnect at {}, is Anki running? ({e})", self.url))?
+            .error_for_status()?
+            .text()
+            .await?;
+        let mut response: Value = serde_json::from_str(&response_text)?;
+        match response.get("error") {
+            Some(Value::Null) | None => Ok(response["result"].take()),
+            Some(error) => Err(format!("AnkiConnect `{action}` failed: {error}").into()),
+        }
+    }
+
+    // an existing model gets the fields it lacks, from decks made by older versions, then its
+    // templates and css replaced by ours
+    async fn ensure_model(&self, templates: &TemplateSet) -> Result<(), Box<dyn Error>> {
+        let model_names = self.request("modelNames", json!({})).await?;
+        let css = templates.css.clone().unwrap_or_default();
+        if model_names
+            .as_array()
+            .is_some_and(|names| names.iter().any(|n| n == MODEL_NAME))
+        {
+            let field_names = self
+                .request("modelFieldNames", json!({ "modelName": MODEL_NAME }))
+                .await?;
+            let field_names = field_names.as_array().cloned().unwrap_or_default();
+            for field_name in FIELD_NAMES {
+                if !field_names.iter().any(|f| f == field_name) {
+                    self.request(
+                        "modelFieldAdd",
+                        json!({ "modelName": MODEL_NAME, "fieldName": field_name }),
+                    )
+                    .await?;
+                }
+            }
+            let card_templates = templates
+                .cards
+                .iter()
+                .map(|c| (c.name.to_string(), json!({ "Front": c.q_format, "Back": c.a_format })))
+                .collect::<Map<String, Value>>();
+            self.request(
+                "updateModelTemplates",
+                json!({ "model": { "name": MODEL_NAME, "templates": card_templates } }),
+            )
+            .await?;
+            self.request(
+                "updateModelStyling",
+                json!({ "model": { "name": MODEL_NAME, "css": css } }),
+            )
+            .await?;
+            return Ok(());
+        }
+        println!("Creating model {MODEL_NAME}...");
+        let card_templates = templates
+            .cards
+            .iter()
+            .map(|c| json!({ "Name": c.name, "Front": c.q_format, "Back": c.a_format }))
+            .collect::<Vec<Value>>();
+        self.request(
+            "createModel",
+            json!({
+                "modelName": MODEL_NAME,
+                "inOrderFields": FIELD_NAMES,
+                "css": css,
+                "isCloze": false,
+                "cardTemplates": card_templates,
+            }),
+        )
+        .await?;
+        Ok(())
+    }
+
+    // notes are found by their search_result, the same key their guid is derived from
+    async fn find_note(&self, search_result: &str) -> Result<Option<Value>, Box<dyn Error>> {
+        let query = note_query(search_result);
+        let note_ids = self.request("findNotes", json!({ "query": query })).await?;
+        let note_ids = match note_ids.as_array() {
+            Some(ids) if !ids.is_empty() => ids.clone(),
+            _ => return Ok(None),
+        };
+        let mut notes_info = self.request("notesInfo", json!({ "notes": note_ids })).await?;
+        Ok(notes_info.get_mut(0).map(Value::take))
+    }
+}
+
+// quotes, wildcards and colons in the search_result would otherwise be read as search syntax
+fn note_query(search_result: &str) -> String {
+    let escaped = search_result
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('*', "\\*")
+        .replace('_', "\\_")
+        .replace(':', "\\:");
+    format!("\"note:{MODEL_NAME}\" \"search_result:{escaped}\"")
+}
+
+// true if the fields or tags of the note in anki differ from ours
+fn note_changed(note_info: &Value, fields: &Map<String, Value>, tags: &[String]) -> bool {
+    let fields_changed = fields
+        .iter()
+        .any(|(name, value)| note_info["fields"][name]["value"] != *value);
+    let note_tags = note_info["tags"]
+        .as_array()
+        .map(|t| t.iter().filter_map(Value::as_str).collect::<HashSet<&str>>())
+        .unwrap_or_default();
+    fields_changed || tags.iter().any(|t| !note_tags.contains(t.as_str()))
+}
+
+pub async fn sync_storage(
+    url: &str,
+    sentence_choice: SentenceChoice,
+    card_types: &[CardType],
+    sub_decks: Option<SubDecks>,
+    templates: &TemplateSet,
+) -> Result<SyncResult, Box<dyn Error>> {
+    let translation_infos = Storage::open()?.in_deck()?;
+    let anki_connect = AnkiConnect::new(url);
+    sync_translation_infos(
+        &anki_connect,
+        &translation_infos,
+        sentence_choice,
+        card_types,
+        sub_decks,
+        templates,
+    )
+    .await
+}
+
+async fn sync_translation_infos(
+    anki_connect: &AnkiConnect,
+    translation_infos: &[TranslationInfo],
+    sentence_choice: SentenceChoice,
+    card_types: &[CardType],
+    sub_decks: Option<SubDecks>,
+    templates: &TemplateSet,
+) -> Result<SyncResult, Box<dyn Error>> {
+    anki_connect.ensure_model(templates).await?;
+
+    let mut result = SyncResult::default();
+    let mut seen_search_results: HashSet<String> = HashSet::new();
+    let mut created_decks: HashSet<String> = HashSet::new();
+    for translation_info in translation_infos {
+        if !seen_search_results.insert(translation_info.search_result.clone()) {
+            continue;
+        }
+        if let Some(p) = audio_media_path(translation_info) {
+            anki_connect
+                .request(
+                    "storeMediaFile",
+                    json!({
+                        "filename": p.file_name().unwrap().to_string_lossy(),
+                        "path": p.to_string_lossy(),
+                    }),
+                )
+                .await?;
+        }
+        let fields = FIELD_NAMES
+            .iter()
+            .map(|name| name.to_string())
+            .zip(note_fields(translation_info, sentence_choice, card_types).into_iter().map(Value::from))
+            .collect::<Map<String, Value>>();
+        let tags = tags(translation_info);
+
+        match anki_connect.find_note(&translation_info.search_result).await? {
+            None => {
+                let deck_name = deck_name(translation_info, sub_decks);
+                if created_decks.insert(deck_name.clone()) {
+                    anki_connect
+                        .request("createDeck", json!({ "deck": deck_name }))
+                        .await?;
+                }
+                anki_connect
+                    .request(
+                        "addNote",
+                        json!({ "note": {
+                            "deckName": deck_name,
+                            "modelName": MODEL_NAME,
+                            "fields": fields,
+                            "tags": tags,
+                            "options": { "allowDuplicate": false },
+                        }}),
+                    )
+                    .await?;
+                result.added.push(translation_info.title.clone());
+            }
+            Some(note_info) if note_changed(&note_info, &fields, &tags) => {
+                let note_id = &note_info["noteId"];
+                anki_connect
+                    .request(
+                        "updateNoteFields",
+                        json!({ "note": { "id": note_id, "fields": fields } }),
+                    )
+                    .await?;
+                anki_connect
+                    .request("addTags", json!({ "notes": [note_id], "tags": tags.join(" ") }))
+                    .await?;
+                result.updated.push(translation_info.title.clone());
+            }
+            Some(_) => result.unchanged.push(translation_info.title.clone()),
+        }
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::anki::{cloze_template, model_templates};
+    use crate::templates::CardTemplate;
+    use std::sync::{Arc, Mutex};
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+
+    // a search_result using every character anki's search syntax gives a meaning to
+    const ODD_SEARCH_RESULT: &str = "a\"b*c_d:e\\f";
+
+    struct StubNote {
+        id: u64,
+        fields: Map<String, Value>,
+        tags: Vec<String>,
+    }
+
+    // what an AnkiConnect stub keeps between requests
+    #[derive(Default)]
+    struct Stub {
+        // name and fields of each model
+        models: Vec<(String, Vec<String>)>,
+        notes: Vec<StubNote>,
+        queries: Vec<String>,
+    }
+
+    // as in anki's search, `\x` matches `x`
+    fn unescape(text: &str) -> String {
+        let mut unescaped = String::new();
+        let mut chars = text.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => unescaped.extend(chars.next()),
+                c => unescaped.push(c),
+            }
+        }
+        unescaped
+    }
+
+    fn handle(stub: &mut Stub, request: &Value) -> Value {
+        let params = &request["params"];
+        match request["action"].as_str().unwrap() {
+            "modelNames" => json!(stub.models.iter().map(|(name, _)| name).collect::<Vec<_>>()),
+            "modelFieldNames" => {
+                let name = params["modelName"].as_str().unwrap();
+                json!(stub.models.iter().find(|(n, _)| n == name).unwrap().1)
+            }
+            "modelFieldAdd" => {
+                let name = params["modelName"].as_str().unwrap();
+                let model = stub.models.iter_mut().find(|(n, _)| n == name).unwrap();
+                model.1.push(params["fieldName"].as_str().unwrap().to_string());
+                Value::Null
+            }
+            "createModel" => {
+                stub.models.push((
+                    params["modelName"].as_str().unwrap().to_string(),
+                    serde_json::from_value(params["inOrderFields"].clone()).unwrap(),
+                ));
+                Value::Null
+            }
+            "updateModelTemplates" | "updateModelStyling" | "createDeck" | "storeMediaFile" => {
+                Value::Null
+            }
+            "findNotes" => {
+                let query = params["query"].as_str().unwrap();
+                stub.queries.push(query.to_string());
+                let (_, value) = query.split_once(" \"search_result:").unwrap();
+                let search_result = unescape(value.strip_suffix('"').unwrap());
+                let ids = stub
+                    .notes
+                    .iter()
+                    .filter(|n| n.fields["search_result"] == search_result.as_str())
+                    .map(|n| n.id)
+                    .collect::<Vec<u64>>();
+                json!(ids)
+            }
+            "notesInfo" => {
+                let notes_info = params["notes"]
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .map(|id| {
+                        let note = stub.notes.iter().find(|n| n.id == id.as_u64().unwrap()).unwrap();
+                        let fields = note
+                            .fields
+                            .iter()
+                            .map(|(name, value)| (name.clone(), json!({ "value": value })))
+                            .collect::<Map<String, Value>>();
+                        json!({ "noteId": note.id, "tags": note.tags, "fields": fields })
+                    })
+                    .collect::<Vec<Value>>();
+                json!(notes_info)
+            }
+            "addNote" => {
+                let id = stub.notes.len() as u64 + 1;
+                stub.notes.push(StubNote {
+                    id,
+                    fields: params["note"]["fields"].as_object().unwrap().clone(),
+                    tags: serde_json::from_value(params["note"]["tags"].clone()).unwrap(),
+                });
+                json!(id)
+            }
+            "updateNoteFields" => {
+                let id = params["note"]["id"].as_u64().unwrap();
+                let note = stub.notes.iter_mut().find(|n| n.id == id).unwrap();
+                for (name, value) in params["note"]["fields"].as_object().unwrap() {
+                    note.fields.insert(name.clone(), value.clone());
+                }
+                Value::Null
+            }
+            "addTags" => {
+                let tags = params["tags"].as_str().unwrap().split_whitespace();
+                for id in params["notes"].as_array().unwrap() {
+                    let note = stub.notes.iter_mut().find(|n| n.id == id.as_u64().unwrap()).unwrap();
+                    note.tags.extend(tags.clone().map(String::from));
+                }
+                Value::Null
+            }
+            action => panic!("unexpected action `{action}`"),
+        }
+    }
+
+    // answers requests over HTTP/1.1, keeping the connection open between them as reqwest expects
+    async fn serve(listener: TcpListener, stub: Arc<Mutex<Stub>>) {
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            let stub = stub.clone();
+            tokio::spawn(async move {
+                let mut reader = BufReader::new(stream);
+                loop {
+                    let mut content_length = 0;
+                    let mut line = String::new();
+                    // the request line, then headers up to an empty line
+                    loop {
+                        line.clear();
+                        if reader.read_line(&mut line).await.unwrap_or(0) == 0 {
+                            return;
+                        }
+                        if line == "\r\n" {
+                            break;
+                        }
+                        if let Some((name, value)) = line.split_once(':') {
+                            if name.eq_ignore_ascii_case("content-length") {
+                                content_length = value.trim().parse().unwrap();
+                            }
+                        }
+                    }
+                    let mut body = vec![0; content_length];
+                    reader.read_exact(&mut body).await.unwrap();
+                    let request: Value = serde_json::from_slice(&body).unwrap();
+                    let result = handle(&mut stub.lock().unwrap(), &request);
+                    let response = json!({ "result": result, "error": null }).to_string();
+                    let response = format!(
+                        "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{response}",
+                        response.len()
+                    );
+                    reader.get_mut().write_all(response.as_bytes()).await.unwrap();
+                }
+            });
+        }
+    }
+
+    fn built_in_templates() -> TemplateSet {
+        let card_template = |(name, q_format, a_format): (&'static str, &str, &str)| CardTemplate {
+            name,
+            q_format: q_format.to_string(),
+            a_format: a_format.to_string(),
+        };
+        TemplateSet {
+            cards: model_templates().into_iter().map(card_template).collect(),
+            cloze: card_template(cloze_template()),
+            css: None,
+        }
+    }
+
+    #[test]
+    fn note_query_escapes_search_syntax() {
+        assert_eq!(
+            note_query(ODD_SEARCH_RESULT),
+            format!("\"note:{MODEL_NAME}\" \"search_result:a\\\"b\\*c\\_d\\:e\\\\f\"")
+        );
+        assert_eq!(
+            note_query("нож"),
+            format!("\"note:{MODEL_NAME}\" \"search_result:нож\"")
+        );
+    }
+
+    #[tokio::test]
+    async fn sync_adds_then_leaves_unchanged_then_updates() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let anki_connect = AnkiConnect::new(&format!("http://{}", listener.local_addr().unwrap()));
+        let stub = Arc::new(Mutex::new(Stub::default()));
+        tokio::spawn(serve(listener, stub.clone()));
+        let templates = built_in_templates();
+        let mut translation_infos = vec![
+            TranslationInfo::for_test("нож", "knife"),
+            TranslationInfo::for_test(ODD_SEARCH_RESULT, "odd"),
+        ];
+
+        let result = sync_translation_infos(
+            &anki_connect,
+            &translation_infos,
+            SentenceChoice::First,
+            &[],
+            None,
+            &templates,
+        )
+        .await
+        .unwrap();
+        assert_eq!(result.added, ["нож", ODD_SEARCH_RESULT]);
+        assert!(result.updated.is_empty() && result.unchanged.is_empty());
+
+        let result = sync_translation_infos(
+            &anki_connect,
+            &translation_infos,
+            SentenceChoice::First,
+            &[],
+            None,
+            &templates,
+        )
+        .await
+        .unwrap();
+        assert!(result.added.is_empty() && result.updated.is_empty());
+        assert_eq!(result.unchanged, ["нож", ODD_SEARCH_RESULT]);
+
+        translation_infos[0].main_translation = String::from("blade");
+        let result = sync_translation_infos(
+            &anki_connect,
+            &translation_infos,
+            SentenceChoice::First,
+            &[],
+            None,
+            &templates,
+        )
+        .await
+        .unwrap();
+        assert!(result.added.is_empty());
+        assert_eq!(result.updated, ["нож"]);
+        assert_eq!(result.unchanged, [ODD_SEARCH_RESULT]);
+
+        let stub = stub.lock().unwrap();
+        assert_eq!(stub.models.len(), 1);
+        assert_eq!(stub.models[0].0, MODEL_NAME);
+        assert_eq!(stub.notes.len(), 2);
+        assert_eq!(stub.notes[0].fields["main_translation"], "blade");
+        assert!(stub.queries.contains(&note_query(ODD_SEARCH_RESULT)));
+    }
+
+    #[tokio::test]
+    async fn sync_adds_the_fields_an_older_model_lacks() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let anki_connect = AnkiConnect::new(&format!("http://{}", listener.local_addr().unwrap()));
+        let old_fields = FIELD_NAMES[..8].iter().map(|f| f.to_string()).collect();
+        let stub = Arc::new(Mutex::new(Stub {
+            models: vec![(MODEL_NAME.to_string(), old_fields)],
+            ..Stub::default()
+        }));
+        tokio::spawn(serve(listener, stub.clone()));
+        let translation_infos = [TranslationInfo::for_test("нож", "knife")];
+
+        let result = sync_translation_infos(
+            &anki_connect,
+            &translation_infos,
+            SentenceChoice::First,
+            &[],
+            None,
+            &built_in_templates(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(result.added, ["нож"]);
+        let stub = stub.lock().unwrap();
+        assert_eq!(stub.models.len(), 1);
+        assert_eq!(stub.models[0].1, FIELD_NAMES);
+    }
+}
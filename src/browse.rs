@@ -0,0 +1,304 @@
+use crate::or::{refetch_translation_info, Fetcher, TranslationInfo};
+use crate::storage::Storage;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Style, Stylize};
+use ratatui::widgets::{Block, List, ListItem, ListState, Paragraph};
+use ratatui::{DefaultTerminal, Frame};
+use std::error::Error;
+
+const HELP: &str = "type to filter · ↑↓ select · PgUp/PgDn scroll · ^T in deck · ^E edit phrase · ^R re-fetch · Del delete · Esc quit";
+
+enum Mode {
+    Browse,
+    // the new user context phrase as it is typed
+    EditPhrase(String),
+    ConfirmDelete,
+}
+
+struct Entry {
+    translation_info: TranslationInfo,
+    in_deck: bool,
+}
+
+impl Entry {
+    // stress marks are ignored, so that `нож` finds `но́ж`
+    fn matches(&self, filter: &str) -> bool {
+        let translation_info = &self.translation_info;
+        [
+            &translation_info.search_query,
+            &translation_info.title,
+            &translation_info.main_translation,
+        ]
+        .into_iter()
+        .chain(&translation_info.other_translations)
+        .any(|field| field.replace('\u{301}', "").to_lowercase().contains(filter))
+    }
+}
+
+struct Browser {
+    storage: Storage,
+    fetcher: Fetcher,
+    entries: Vec<Entry>,
+    filter: String,
+    // indexes into `entries` of the ones matching the filter
+    shown: Vec<usize>,
+    list_state: ListState,
+    card_scroll: u16,
+    mode: Mode,
+    status: Option<String>,
+}
+
+impl Browser {
+    fn new() -> Result<Self, Box<dyn Error>> {
+        let storage = Storage::open()?;
+        let entries = storage
+            .all_with_in_deck()?
+            .into_iter()
+            .map(|(translation_info, in_deck)| Entry {
+                translation_info,
+                in_deck,
+            })
+            .collect();
+        let mut browser = Self {
+            storage,
+            fetcher: Fetcher::new(None),
+            entries,
+            filter: String::new(),
+            shown: vec![],
+            list_state: ListState::default(),
+            card_scroll: 0,
+            mode: Mode::Browse,
+            status: None,
+        };
+        browser.apply_filter();
+        Ok(browser)
+    }
+
+    fn apply_filter(&mut self) {
+        let filter = self.filter.to_lowercase();
+        self.shown = (0..self.entries.len())
+            .filter(|i| self.entries[*i].matches(&filter))
+            .collect();
+        self.list_state.select(match self.shown.is_empty() {
+            true => None,
+            false => Some(0),
+        });
+        self.card_scroll = 0;
+    }
+
+    fn selected(&self) -> Option<usize> {
+        self.list_state.selected().map(|i| self.shown[i])
+    }
+
+    fn select_next(&mut self, forward: bool) {
+        if let Some(i) = self.list_state.selected() {
+            let i = match forward {
+                true => (i + 1).min(self.shown.len() - 1),
+                false => i.saturating_sub(1),
+            };
+            self.list_state.select(Some(i));
+            self.card_scroll = 0;
+        }
+    }
+
+    fn toggle_in_deck(&mut self, i: usize) -> Result<(), Box<dyn Error>> {
+        let entry = &mut self.entries[i];
+        entry.in_deck = !entry.in_deck;
+        self.storage.set_in_deck(
+            &entry.translation_info.search_query,
+            &entry.translation_info.search_result,
+            entry.in_deck,
+        )?;
+        self.status = Some(match entry.in_deck {
+            true => format!("{} goes into the deck.", entry.translation_info.title),
+            false => format!("{} is left out of the deck.", entry.translation_info.title),
+        });
+        Ok(())
+    }
+
+    fn delete(&mut self, i: usize) -> Result<(), Box<dyn Error>> {
+        let entry = self.entries.remove(i);
+        self.storage.remove_entry(
+            &entry.translation_info.search_query,
+            &entry.translation_info.search_result,
+        )?;
+        self.status = Some(format!("Removed {}.", entry.translation_info.title));
+        let selected = self.list_state.selected();
+        self.apply_filter();
+        if let Some(selected) = selected {
+            self.list_state
+                .select(Some(selected.min(self.shown.len().saturating_sub(1))));
+        }
+        Ok(())
+    }
+
+    fn save_phrase(&mut self, i: usize, phrase: &str) -> Result<(), Box<dyn Error>> {
+        let translation_info = &mut self.entries[i].translation_info;
+        translation_info.user_context_phrase = match phrase.trim() {
+            "" => None,
+            phrase => Some(phrase.to_string()),
+        };
+        self.storage.update(translation_info)?;
+        self.status = Some(format!("Saved the phrase of {}.", translation_info.title));
+        Ok(())
+    }
+
+    async fn refetch(&mut self, i: usize) -> Result<(), Box<dyn Error>> {
+        let refetched =
+            refetch_translation_info(&self.fetcher, &self.entries[i].translation_info).await?;
+        self.storage.update(&refetched)?;
+        self.status = Some(format!("Fetched {} again.", refetched.title));
+        self.entries[i].translation_info = refetched;
+        Ok(())
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [main_area, input_area, status_area] = Layout::vertical([
+            Constraint::Min(1),
+            Constraint::Length(1),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+        let [list_area, card_area] =
+            Layout::horizontal([Constraint::Percentage(35), Constraint::Percentage(65)])
+                .areas(main_area);
+
+        let items: Vec<ListItem> = self
+            .shown
+            .iter()
+            .map(|i| {
+                let entry = &self.entries[*i];
+                let mark = match entry.in_deck {
+                    true => "[x]",
+                    false => "[ ]",
+                };
+                let item = ListItem::new(format!(
+                    "{mark} {}  {}",
+                    entry.translation_info.title, entry.translation_info.main_translation
+                ));
+                match entry.in_deck {
+                    true => item,
+                    false => item.dim(),
+                }
+            })
+            .collect();
+        let list = List::new(items)
+            .block(Block::bordered().title(format!(" {}/{} ", self.shown.len(), self.entries.len())))
+            .highlight_style(Style::new().reversed());
+        frame.render_stateful_widget(list, list_area, &mut self.list_state);
+
+        let card = match self.selected() {
+            Some(i) => self.entries[i].translation_info.to_string(),
+            None => String::from("No saved search matches the filter."),
+        };
+        frame.render_widget(
+            Paragraph::new(card)
+                .block(Block::bordered())
+                .scroll((self.card_scroll, 0)),
+            card_area,
+        );
+
+        let input = match &self.mode {
+            Mode::Browse => format!("/{}", self.filter),
+            Mode::EditPhrase(phrase) => format!("phrase: {phrase}"),
+            Mode::ConfirmDelete => String::from("delete? (y/n)"),
+        };
+        frame.render_widget(Paragraph::new(input), input_area);
+        let status = self.status.as_deref().unwrap_or(HELP);
+        frame.render_widget(Paragraph::new(status).dim(), status_area);
+    }
+
+    // returns false when the browser should close
+    async fn handle_key(
+        &mut self,
+        key: KeyEvent,
+        terminal: &mut DefaultTerminal,
+    ) -> Result<bool, Box<dyn Error>> {
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        match &mut self.mode {
+            Mode::EditPhrase(phrase) => match key.code {
+                KeyCode::Enter => {
+                    let phrase = phrase.clone();
+                    self.mode = Mode::Browse;
+                    if let Some(i) = self.selected() {
+                        self.save_phrase(i, &phrase)?;
+                    }
+                }
+                KeyCode::Esc => self.mode = Mode::Browse,
+                KeyCode::Backspace => {
+                    phrase.pop();
+                }
+                KeyCode::Char(c) if !ctrl => phrase.push(c),
+                _ => {}
+            },
+            Mode::ConfirmDelete => {
+                self.mode = Mode::Browse;
+                if let (KeyCode::Char('y'), Some(i)) = (key.code, self.selected()) {
+                    self.delete(i)?;
+                }
+            }
+            Mode::Browse => {
+                self.status = None;
+                match (key.code, self.selected()) {
+                    (KeyCode::Char('c'), _) if ctrl => return Ok(false),
+                    (KeyCode::Esc, _) if self.filter.is_empty() => return Ok(false),
+                    (KeyCode::Esc, _) => {
+                        self.filter.clear();
+                        self.apply_filter();
+                    }
+                    (KeyCode::Up, _) => self.select_next(false),
+                    (KeyCode::Down, _) => self.select_next(true),
+                    (KeyCode::PageUp, _) => self.card_scroll = self.card_scroll.saturating_sub(10),
+                    (KeyCode::PageDown, _) => self.card_scroll = self.card_scroll.saturating_add(10),
+                    (KeyCode::Char('t'), Some(i)) if ctrl => self.toggle_in_deck(i)?,
+                    (KeyCode::Char('e'), Some(i)) if ctrl => {
+                        let translation_info = &self.entries[i].translation_info;
+                        let phrase = translation_info.user_context_phrase.clone().unwrap_or_default();
+                        self.mode = Mode::EditPhrase(phrase);
+                    }
+                    (KeyCode::Char('r'), Some(i)) if ctrl => {
+                        self.status = Some(String::from("Fetching..."));
+                        terminal.draw(|frame| self.draw(frame))?;
+                        self.refetch(i).await?;
+                    }
+                    (KeyCode::Delete, Some(_)) => self.mode = Mode::ConfirmDelete,
+                    (KeyCode::Backspace, _) => {
+                        self.filter.pop();
+                        self.apply_filter();
+                    }
+                    (KeyCode::Char(c), _) if !ctrl => {
+                        self.filter.push(c);
+                        self.apply_filter();
+                    }
+                    _ => {}
+                }
+            }
+        }
+        Ok(true)
+    }
+
+    async fn run(&mut self, terminal: &mut DefaultTerminal) -> Result<(), Box<dyn Error>> {
+        loop {
+            terminal.draw(|frame| self.draw(frame))?;
+            let key = match event::read()? {
+                Event::Key(key) if key.kind == KeyEventKind::Press => key,
+                _ => continue,
+            };
+            // a failed action is shown, the browser stays open
+            match self.handle_key(key, terminal).await {
+                Ok(true) => {}
+                Ok(false) => return Ok(()),
+                Err(error) => self.status = Some(format!("error: {error}")),
+            }
+        }
+    }
+}
+
+pub async fn browse() -> Result<(), Box<dyn Error>> {
+    let mut browser = Browser::new()?;
+    let mut terminal = ratatui::init();
+    let result = browser.run(&mut terminal).await;
+    ratatui::restore();
+    result
+}
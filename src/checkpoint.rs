@@ -0,0 +1,113 @@
+use crate::anki::stable_hash;
+use crate::utils::get_checkpoint_dir;
+use std::error::Error;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+pub enum Outcome {
+    Fetched,
+    Existent,
+    Failed(String),
+}
+
+pub struct CheckpointEntry {
+    pub line_number: usize,
+    pub search_query: String,
+    pub outcome: Outcome,
+}
+
+// one line per processed list line, `line_number\toutcome\tsearch_query[\treason]`,
+// flushed as soon as it is known so an interrupted run loses nothing
+pub struct Checkpoint {
+    path: PathBuf,
+    file: File,
+}
+
+// the checkpoint file of `source`, named after its full path, and the header it starts with
+fn checkpoint_path(source: &Path) -> Result<(PathBuf, String), Box<dyn Error>> {
+    let source = fs::canonicalize(source)?;
+    let header = format!("# {}", source.display());
+    let file_name = format!("{:016x}.checkpoint", stable_hash(&header));
+    Ok((get_checkpoint_dir()?.join(file_name), header))
+}
+
+impl Checkpoint {
+    // starts a new checkpoint for `source`, dropping any previous one over the same file
+    pub fn create(source: &Path) -> Result<Self, Box<dyn Error>> {
+        let (path, header) = checkpoint_path(source)?;
+        let mut file = File::create(&path)?;
+        writeln!(file, "{header}")?;
+        Ok(Self { path, file })
+    }
+
+    // reopens the checkpoint of an interrupted run over `source`
+    pub fn resume(source: &Path) -> Result<(Self, Vec<CheckpointEntry>), Box<dyn Error>> {
+        let (path, header) = checkpoint_path(source)?;
+        if !path.is_file() {
+            return Err(format!(
+                "No interrupted batch run over `{}` to resume.",
+                source.display()
+            )
+            .into());
+        }
+        let mut lines = BufReader::new(File::open(&path)?).lines();
+        // two paths with the same hash
+        if lines.next().transpose()?.as_ref() != Some(&header) {
+            return Err(format!(
+                "The checkpoint {} is not for `{}`.",
+                path.display(),
+                source.display()
+            )
+            .into());
+        }
+        let mut entries = vec![];
+        for line in lines {
+            // a line cut by the interruption is just processed again
+            if let Some(entry) = Self::parse_line(&line?) {
+                entries.push(entry);
+            }
+        }
+        let file = OpenOptions::new().append(true).open(&path)?;
+        Ok((Self { path, file }, entries))
+    }
+
+    fn parse_line(line: &str) -> Option<CheckpointEntry> {
+        let mut columns = line.splitn(4, '\t');
+        let line_number = columns.next()?.parse().ok()?;
+        let outcome = columns.next()?;
+        let search_query = columns.next()?.to_string();
+        let outcome = match outcome {
+            "fetched" => Outcome::Fetched,
+            "existent" => Outcome::Existent,
+            "failed" => Outcome::Failed(columns.next()?.to_string()),
+            _ => return None,
+        };
+        Some(CheckpointEntry {
+            line_number,
+            search_query,
+            outcome,
+        })
+    }
+
+    pub fn record(&mut self, entry: &CheckpointEntry) -> Result<(), Box<dyn Error>> {
+        let line_number = entry.line_number;
+        let search_query = &entry.search_query;
+        match &entry.outcome {
+            Outcome::Fetched => writeln!(self.file, "{line_number}\tfetched\t{search_query}")?,
+            Outcome::Existent => writeln!(self.file, "{line_number}\texistent\t{search_query}")?,
+            Outcome::Failed(reason) => {
+                writeln!(self.file, "{line_number}\tfailed\t{search_query}\t{reason}")?
+            }
+        }
+        self.file.flush()?;
+        Ok(())
+    }
+
+    // the run finished, nothing left to resume
+    pub fn finish(self) -> Result<(), Box<dyn Error>> {
+        drop(self.file);
+        fs::remove_file(self.path)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,259 @@
+use crate::anki::{CardType, SentenceChoice, SubDecks};
+use crate::ankiconnect::DEFAULT_URL;
+use crate::config::check_max_rps;
+use crate::export::ExportFormat;
+use crate::or::Pick;
+use crate::output::SearchOutput;
+use crate::review::Direction;
+use clap::{Args, Parser, Subcommand};
+use clap_complete::Shell;
+use std::path::PathBuf;
+
+#[derive(Parser)]
+#[command(
+    name = "oraki",
+    version,
+    about = "Search queries on OpenRussian and create an Anki deck with the results."
+)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Shortcut for `oraki search <QUERY>...`.
+    pub query: Vec<String>,
+
+    /// Read the configuration from this file instead of $HOME/.config/oraki/config.toml.
+    #[arg(long, global = true, value_name = "FILE")]
+    pub config: Option<PathBuf>,
+
+    /// Use this collection instead of the default one.
+    #[arg(long, global = true, value_name = "NAME")]
+    pub collection: Option<String>,
+
+    /// Look words up in the index made by `oraki db import`, without network.
+    #[arg(long, global = true)]
+    pub offline: bool,
+
+    /// Look words up on OpenRussian even when `offline` is set in the config.
+    #[arg(long, global = true, conflicts_with = "offline")]
+    pub online: bool,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Search a word or expression, english or russian, and save the result.
+    Search {
+        #[arg(required = true)]
+        query: Vec<String>,
+        /// How many example sentences to show.
+        #[arg(short, long, default_value_t = 1)]
+        sentences: usize,
+        /// List every matching word and choose which ones to save.
+        #[arg(short, long, conflicts_with_all = ["pick", "all"])]
+        interactive: bool,
+        /// How to print the results; `json` and `plain` also list every matching word.
+        #[arg(long, value_enum, default_value_t = SearchOutput::Pretty, conflicts_with = "interactive")]
+        output: SearchOutput,
+        #[command(flatten)]
+        pick: PickArgs,
+    },
+    /// Open a prompt to search words one after another, also run by `oraki` alone.
+    Repl,
+    /// Do multiple searches, one for each line of the file.
+    ///
+    /// The first word of a line is the search query and the rest is used as
+    /// the context phrase. To search for an expression, separate it from the
+    /// context phrase with a tab.
+    Batch {
+        #[arg(required_unless_present = "retry_failed", conflicts_with = "retry_failed")]
+        file: Option<PathBuf>,
+        /// Also keep the scraped context phrase when the line has one.
+        #[arg(long)]
+        keep_both: bool,
+        /// Where to write the queries that failed.
+        #[arg(long, default_value = "failed.out")]
+        failed_output: PathBuf,
+        /// How many searches to run at once [default: 4, or `jobs` in the config].
+        #[arg(short, long)]
+        jobs: Option<usize>,
+        /// Maximum number of requests per second sent to OpenRussian, 0 for no limit
+        /// [default: 5, or `max_rps` in the config].
+        #[arg(long, value_parser = parse_max_rps)]
+        max_rps: Option<f64>,
+        /// Continue the last batch run over the same file where it was interrupted.
+        #[arg(long)]
+        resume: bool,
+        /// Search again only the queries in the failed output of a previous run.
+        #[arg(long)]
+        retry_failed: bool,
+        /// Tag every entry in the file with this name, e.g. the book or course it comes from.
+        #[arg(long)]
+        source: Option<String>,
+        #[command(flatten)]
+        pick: PickArgs,
+    },
+    /// Compile saved searches into $HOME/.local/share/oraki/output.apkg.
+    Compile {
+        /// Which example sentence goes on the card.
+        #[arg(long, value_enum, default_value_t = SentenceChoice::First)]
+        sentence: SentenceChoice,
+        /// Extra cards to generate for each word, besides the default one
+        /// [default: `cards` in the config].
+        #[arg(long, value_enum, value_delimiter = ',')]
+        cards: Vec<CardType>,
+        /// Make cloze notes from the context phrase, when the word can be found in it.
+        #[arg(long)]
+        cloze: bool,
+        /// Split the deck into sub-decks, by part of speech or by batch source.
+        #[arg(long, value_enum)]
+        subdecks: Option<SubDecks>,
+        /// Template set to use, a directory in $HOME/.local/share/oraki/templates/ or a path.
+        #[arg(long)]
+        templates: Option<String>,
+    },
+    /// Add and update the saved searches in a running Anki, through the AnkiConnect add-on.
+    Sync {
+        /// Address AnkiConnect listens on.
+        #[arg(long, default_value = DEFAULT_URL)]
+        url: String,
+        /// Which example sentence goes on the card.
+        #[arg(long, value_enum, default_value_t = SentenceChoice::First)]
+        sentence: SentenceChoice,
+        /// Extra cards to generate for each word, besides the default one
+        /// [default: `cards` in the config].
+        #[arg(long, value_enum, value_delimiter = ',')]
+        cards: Vec<CardType>,
+        /// Put new notes in sub-decks, by part of speech or by batch source.
+        #[arg(long, value_enum)]
+        subdecks: Option<SubDecks>,
+        /// Template set to use, a directory in $HOME/.local/share/oraki/templates/ or a path.
+        #[arg(long)]
+        templates: Option<String>,
+    },
+    /// List saved searches.
+    List,
+    /// Remove a saved search.
+    Remove {
+        #[arg(required = true)]
+        query: Vec<String>,
+    },
+    /// Browse the saved searches in a full-screen list, to filter, edit, re-fetch or delete them.
+    Browse,
+    /// Quiz yourself on the saved searches in the terminal, with spaced repetition.
+    Review {
+        /// What is shown and what is typed as the answer.
+        #[arg(long, value_enum, default_value_t = Direction::Both)]
+        direction: Direction,
+        /// Most words already seen to review in one session.
+        #[arg(long, default_value_t = 50)]
+        limit: usize,
+        /// Most words never reviewed to add in one session.
+        #[arg(long, default_value_t = 10)]
+        new: usize,
+    },
+    /// Import an old pipe-delimited main.csv into the saved searches.
+    Import { file: PathBuf },
+    /// Export the saved searches of the collection, as csv by default.
+    Export {
+        /// Write to this file instead of stdout.
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        #[arg(short, long, value_enum, default_value_t = ExportFormat::Csv)]
+        format: ExportFormat,
+        /// Only export entries with this tag, as given to the notes; can be repeated.
+        #[arg(long, value_name = "TAG")]
+        tag: Vec<String>,
+        /// Only export entries saved on or after this date (YYYY-MM-DD).
+        #[arg(long, value_name = "DATE")]
+        since: Option<String>,
+        /// Only export entries saved on or before this date (YYYY-MM-DD).
+        #[arg(long, value_name = "DATE")]
+        until: Option<String>,
+    },
+    /// Print shell completions.
+    Completions { shell: Shell },
+    /// Manage collections, each with its own saved searches, deck and output file.
+    Collection {
+        #[command(subcommand)]
+        command: CollectionCommand,
+    },
+    /// Manage the offline index of the OpenRussian dictionary.
+    Db {
+        #[command(subcommand)]
+        command: DbCommand,
+    },
+    /// Inspect the configuration.
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum CollectionCommand {
+    /// List collections and how many saved searches they have.
+    List,
+    /// Create a new collection.
+    Create {
+        name: String,
+        /// Name of its Anki deck [default: the deck name followed by the collection name].
+        #[arg(long)]
+        deck_name: Option<String>,
+    },
+    /// Copy the saved searches of a collection into another.
+    Merge { source: String, destination: String },
+    /// Delete a collection and everything saved in it.
+    Delete {
+        name: String,
+        /// Don't ask for confirmation.
+        #[arg(short, long)]
+        yes: bool,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum DbCommand {
+    /// Load the csv files of the OpenRussian dictionary dump in a directory.
+    ///
+    /// words.csv and translations.csv are required; sentences, declensions,
+    /// conjugations and the other tables are used when present.
+    Import { dir: PathBuf },
+}
+
+#[derive(Subcommand)]
+pub enum ConfigCommand {
+    /// Print the configuration in use, with the defaults for anything not set.
+    Show,
+}
+
+// when a search query matches more than one word, the first is saved by default
+#[derive(Args)]
+pub struct PickArgs {
+    /// Save the Nth matching word instead of the first.
+    #[arg(
+        long,
+        value_name = "N",
+        conflicts_with = "all",
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..)
+    )]
+    pub pick: Option<usize>,
+    /// Save every matching word.
+    #[arg(long)]
+    pub all: bool,
+}
+
+impl PickArgs {
+    pub fn to_pick(&self, interactive: bool) -> Pick {
+        match (self.pick, self.all, interactive) {
+            (_, _, true) => Pick::Interactive,
+            (Some(n), _, _) => Pick::Nth(n),
+            (None, true, _) => Pick::All,
+            (None, false, _) => Pick::First,
+        }
+    }
+}
+
+fn parse_max_rps(value: &str) -> Result<f64, String> {
+    let max_rps = value.parse::<f64>().map_err(|e| e.to_string())?;
+    check_max_rps(max_rps)
+}
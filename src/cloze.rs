@@ -0,0 +1,131 @@
+use crate::or::TranslationInfo;
+
+// stress marks and ё are not always written, so they are ignored when comparing
+pub fn normalize(word: &str) -> String {
+    word.chars()
+        .filter(|c| *c != '\u{301}')
+        .flat_map(|c| c.to_lowercase())
+        .map(|c| if c == 'ё' { 'е' } else { c })
+        .collect()
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphabetic() || c == '\u{301}' || c == '-'
+}
+
+// every form of the word we know of: the lemma plus declension and conjugation tables
+fn known_forms(translation_info: &TranslationInfo) -> Vec<String> {
+    let mut forms = vec![translation_info.title.clone()];
+    if let Some(declension) = &translation_info.declension {
+        for row in &declension.rows {
+            forms.extend(row.forms.iter().cloned());
+        }
+    }
+    if let Some(verb) = &translation_info.verb {
+        for verb_form in verb.conjugation.iter().chain(&verb.past).chain(&verb.imperative) {
+            forms.push(verb_form.form.clone());
+        }
+    }
+    forms
+        .iter()
+        .flat_map(|f| f.split(" / "))
+        .flat_map(|f| f.split_whitespace())
+        .map(normalize)
+        .filter(|f| !f.is_empty())
+        .collect()
+}
+
+// without tables, words that start like the lemma minus its ending are taken as inflections
+fn matches_lemma_stem(word: &str, lemma: &str) -> bool {
+    let without_suffix = ["ться", "ть", "ся"]
+        .iter()
+        .find_map(|suffix| lemma.strip_suffix(suffix))
+        .unwrap_or(lemma);
+    let stem = without_suffix.trim_end_matches(|c| "аеёиоуыэюяйь".contains(c));
+    let n_stem = stem.chars().count();
+    if n_stem < 3 {
+        return word == lemma;
+    }
+    word.starts_with(stem) && word.chars().count() <= n_stem + 4
+}
+
+// the sentence with the first form of the word blanked, None if the word isn't in it
+pub fn make_cloze(sentence: &str, translation_info: &TranslationInfo) -> Option<String> {
+    let forms = known_forms(translation_info);
+    let lemma = normalize(&translation_info.title);
+    let has_tables = translation_info.declension.is_some() || translation_info.verb.is_some();
+
+    let mut start = None;
+    for (i, c) in sentence.char_indices().chain([(sentence.len(), ' ')]) {
+        match (is_word_char(c), start) {
+            (true, None) => start = Some(i),
+            (false, Some(s)) => {
+                let word = &sentence[s..i];
+                let normalized = normalize(word);
+                if forms.contains(&normalized)
+                    || (!has_tables && matches_lemma_stem(&normalized, &lemma))
+                {
+                    return Some(format!(
+                        "{}{{{{c1::{}}}}}{}",
+                        &sentence[..s],
+                        word,
+                        &sentence[i..]
+                    ));
+                }
+                start = None;
+            }
+            _ => (),
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inflected_forms_match_the_lemma_stem() {
+        assert!(matches_lemma_stem("ножом", "нож"));
+        assert!(matches_lemma_stem("делаю", "делать"));
+        assert!(matches_lemma_stem("читала", "читать"));
+        assert!(!matches_lemma_stem("ножницами", "нож"));
+        assert!(!matches_lemma_stem("вилка", "нож"));
+        // too short a stem to guess from, only the lemma itself matches
+        assert!(matches_lemma_stem("дом", "дом"));
+        assert!(!matches_lemma_stem("дома", "да"));
+    }
+
+    #[test]
+    fn cloze_blanks_an_inflected_form() {
+        assert_eq!(
+            make_cloze("Он режет хлеб ножом.", &TranslationInfo::for_test("но́ж", "")).as_deref(),
+            Some("Он режет хлеб {{c1::ножом}}.")
+        );
+    }
+
+    #[test]
+    fn cloze_ignores_stress_marks_in_the_sentence() {
+        assert_eq!(
+            make_cloze("Где мо́й но́ж?", &TranslationInfo::for_test("но́ж", "")).as_deref(),
+            Some("Где мо́й {{c1::но́ж}}?")
+        );
+    }
+
+    #[test]
+    fn cloze_takes_e_for_yo() {
+        assert_eq!(
+            make_cloze("Мы купили елку.", &TranslationInfo::for_test("ёлка", "")).as_deref(),
+            Some("Мы купили {{c1::елку}}.")
+        );
+        assert_eq!(
+            make_cloze("Мы купили ёлку.", &TranslationInfo::for_test("елка", "")).as_deref(),
+            Some("Мы купили {{c1::ёлку}}.")
+        );
+    }
+
+    #[test]
+    fn cloze_is_none_without_the_word() {
+        assert_eq!(make_cloze("Он взял вилку.", &TranslationInfo::for_test("но́ж", "")), None);
+    }
+}
@@ -0,0 +1,112 @@
+use crate::anki::deck_id_for;
+use crate::config::Config;
+use crate::storage::Storage;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+// the collection kept directly in the data dir, as before collections existed
+pub const DEFAULT_COLLECTION: &str = "default";
+const SETTINGS_FILE_NAME: &str = "collection.toml";
+
+// kept in collection.toml inside the collection dir, written by `create`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CollectionSettings {
+    pub deck_name: String,
+    pub deck_id: i64,
+    // defaults to output.apkg inside the collection dir
+    pub output: Option<PathBuf>,
+}
+
+pub fn collections_dir(data_dir: &Path) -> PathBuf {
+    data_dir.join("collections/")
+}
+
+pub fn collection_dir(data_dir: &Path, name: &str) -> PathBuf {
+    match name {
+        DEFAULT_COLLECTION => data_dir.to_path_buf(),
+        name => collections_dir(data_dir).join(name),
+    }
+}
+
+fn check_name(name: &str) -> Result<(), Box<dyn Error>> {
+    if name.is_empty() || name.starts_with('.') || name.contains(['/', '\\']) {
+        return Err(format!("`{name}` can't be used as a collection name.").into());
+    }
+    Ok(())
+}
+
+pub fn check_exists(data_dir: &Path, name: &str) -> Result<PathBuf, Box<dyn Error>> {
+    check_name(name)?;
+    let dir = collection_dir(data_dir, name);
+    if !dir.join(SETTINGS_FILE_NAME).is_file() && name != DEFAULT_COLLECTION {
+        return Err(format!(
+            "No collection `{name}`, create it with `oraki collection create {name}`."
+        )
+        .into());
+    }
+    Ok(dir)
+}
+
+impl CollectionSettings {
+    pub fn read(data_dir: &Path, name: &str) -> Result<Self, Box<dyn Error>> {
+        let dir = check_exists(data_dir, name)?;
+        let text = std::fs::read_to_string(dir.join(SETTINGS_FILE_NAME))?;
+        Ok(toml::from_str(&text)?)
+    }
+}
+
+// every collection name, the default one first
+pub fn names(data_dir: &Path) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut names = vec![];
+    if let Ok(entries) = std::fs::read_dir(collections_dir(data_dir)) {
+        for entry in entries {
+            let path = entry?.path();
+            if path.join(SETTINGS_FILE_NAME).is_file() {
+                names.push(path.file_name().unwrap().to_string_lossy().to_string());
+            }
+        }
+    }
+    names.sort();
+    names.insert(0, String::from(DEFAULT_COLLECTION));
+    Ok(names)
+}
+
+// the deck gets its own id, so it can be imported next to the others
+pub fn create(config: &Config, name: &str, deck_name: Option<&str>) -> Result<(), Box<dyn Error>> {
+    check_name(name)?;
+    let dir = collection_dir(&config.data_dir, name);
+    if name == DEFAULT_COLLECTION || dir.exists() {
+        return Err(format!("Collection `{name}` already exists.").into());
+    }
+    let deck_name = deck_name
+        .map(String::from)
+        .unwrap_or_else(|| format!("{} ({name})", config.deck_name));
+    let settings = CollectionSettings {
+        deck_id: deck_id_for(&deck_name),
+        deck_name,
+        output: None,
+    };
+    std::fs::create_dir_all(&dir)?;
+    std::fs::write(dir.join(SETTINGS_FILE_NAME), toml::to_string(&settings)?)?;
+    Ok(())
+}
+
+// copies every entry of `source` missing from `destination`, returns how many
+pub fn merge(data_dir: &Path, source: &str, destination: &str) -> Result<usize, Box<dyn Error>> {
+    if source == destination {
+        return Err("Can't merge a collection into itself.".into());
+    }
+    let source_storage = Storage::open_path(&check_exists(data_dir, source)?.join("main.db"))?;
+    let mut destination_storage =
+        Storage::open_path(&check_exists(data_dir, destination)?.join("main.db"))?;
+    destination_storage.merge_from(&source_storage)
+}
+
+pub fn delete(data_dir: &Path, name: &str) -> Result<(), Box<dyn Error>> {
+    if name == DEFAULT_COLLECTION {
+        return Err("The default collection can't be deleted.".into());
+    }
+    std::fs::remove_dir_all(check_exists(data_dir, name)?)?;
+    Ok(())
+}
@@ -0,0 +1,137 @@
+use crate::anki::{CardType, DECK_ID, DECK_NAME};
+use crate::collection::{collection_dir, CollectionSettings, DEFAULT_COLLECTION};
+use crate::or::HEADER_USER_AGENT;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::Duration;
+
+// pages are parsed by their english text: other translations, aspects and tags
+const LANGUAGES: [&str; 1] = ["en"];
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+// read from $XDG_CONFIG_HOME/oraki/config.toml, every key is optional
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub data_dir: PathBuf,
+    // defaults to output.apkg inside the data dir
+    pub output: Option<PathBuf>,
+    pub deck_name: String,
+    pub deck_id: i64,
+    pub user_agent: String,
+    // language of the translations, as supported by OpenRussian
+    pub language: String,
+    pub jobs: usize,
+    pub max_rps: f64,
+    pub cards: Vec<CardType>,
+    // look words up in the index from `oraki db import` instead of OpenRussian
+    pub offline: bool,
+    // collection used when `--collection` isn't given
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub collection: Option<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            data_dir: dirs::data_dir().unwrap().join("oraki/"),
+            output: None,
+            deck_name: DECK_NAME.to_string(),
+            deck_id: DECK_ID,
+            user_agent: HEADER_USER_AGENT.to_string(),
+            language: String::from("en"),
+            jobs: 4,
+            max_rps: 5.0,
+            cards: vec![],
+            offline: false,
+            collection: None,
+        }
+    }
+}
+
+// 0 is no limit, anything else must give a time between requests that can be waited for
+pub fn check_max_rps(max_rps: f64) -> Result<f64, String> {
+    let is_valid = max_rps == 0.0
+        || (max_rps > 0.0
+            && Duration::try_from_secs_f64(1.0 / max_rps).is_ok_and(|d| !d.is_zero()));
+    match is_valid {
+        true => Ok(max_rps),
+        false => Err(String::from(
+            "the rate must be 0 for no limit, or a positive number of requests per second.",
+        )),
+    }
+}
+
+// `~/` isn't expanded by toml
+fn expand_home(path: &Path) -> PathBuf {
+    match path.strip_prefix("~") {
+        Ok(rest) => dirs::home_dir().unwrap().join(rest),
+        Err(_) => path.to_path_buf(),
+    }
+}
+
+pub fn default_config_path() -> PathBuf {
+    dirs::config_dir().unwrap().join("oraki/config.toml")
+}
+
+impl Config {
+    // a missing file is only an error when its path was given explicitly
+    pub fn load(path: Option<&Path>) -> Result<Self, Box<dyn Error>> {
+        let config_path = path.map(Path::to_path_buf).unwrap_or_else(default_config_path);
+        let mut config: Config = match std::fs::read_to_string(&config_path) {
+            Ok(text) => toml::from_str(&text)
+                .map_err(|e| format!("Invalid config {}: {e}", config_path.display()))?,
+            Err(_) if path.is_none() => Config::default(),
+            Err(e) => return Err(format!("Could not read {}: {e}", config_path.display()).into()),
+        };
+        if !LANGUAGES.contains(&config.language.as_str()) {
+            return Err(format!(
+                "Unsupported language `{}`, use one of: {}.",
+                config.language,
+                LANGUAGES.join(", ")
+            )
+            .into());
+        }
+        check_max_rps(config.max_rps)
+            .map_err(|e| format!("Invalid max_rps in {}: {e}", config_path.display()))?;
+        config.data_dir = expand_home(&config.data_dir);
+        config.output = Some(match &config.output {
+            Some(output) => expand_home(output),
+            None => config.data_dir.join("output.apkg"),
+        });
+        Ok(config)
+    }
+
+    // paths and deck become those of the collection
+    pub fn use_collection(&mut self, name: &str) -> Result<(), Box<dyn Error>> {
+        if name == DEFAULT_COLLECTION {
+            self.collection = None;
+            return Ok(());
+        }
+        let settings = CollectionSettings::read(&self.data_dir, name)?;
+        self.deck_name = settings.deck_name;
+        self.deck_id = settings.deck_id;
+        self.output = Some(match settings.output {
+            Some(output) => expand_home(&output),
+            None => collection_dir(&self.data_dir, name).join("output.apkg"),
+        });
+        self.collection = Some(name.to_string());
+        Ok(())
+    }
+
+    pub fn to_toml(&self) -> Result<String, Box<dyn Error>> {
+        Ok(toml::to_string(self)?)
+    }
+}
+
+// set once in main, before anything reads it
+pub fn init(config: Config) {
+    CONFIG.set(config).expect("config was already set");
+}
+
+pub fn get() -> &'static Config {
+    CONFIG.get_or_init(Config::default)
+}
@@ -0,0 +1,64 @@
+use crate::scrape::{cell_forms, element_text};
+use crate::table::{box_table, html_table};
+use scraper::{Html, Selector};
+use serde::{Deserialize, Serialize};
+
+// nouns have singular and plural columns, adjectives one per gender plus plural
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Declension {
+    pub columns: Vec<String>,
+    pub rows: Vec<DeclensionRow>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DeclensionRow {
+    pub case: String,
+    pub forms: Vec<String>,
+}
+
+impl Declension {
+    pub fn from_response_text(response_text: &str) -> Option<Self> {
+        let document = Html::parse_document(response_text);
+        let table_selector = Selector::parse(".declension table").unwrap();
+        let header_selector = Selector::parse("thead th").unwrap();
+        let row_selector = Selector::parse("tbody tr").unwrap();
+        let th_selector = Selector::parse("th").unwrap();
+        let td_selector = Selector::parse("td").unwrap();
+
+        let table = document.select(&table_selector).next()?;
+        let columns = table
+            .select(&header_selector)
+            .skip(1)
+            .map(element_text)
+            .collect::<Vec<String>>();
+        let rows = table
+            .select(&row_selector)
+            .filter_map(|row| {
+                let case = element_text(row.select(&th_selector).next()?);
+                let mut forms = row.select(&td_selector).map(cell_forms).collect::<Vec<String>>();
+                forms.resize(columns.len(), String::new());
+                Some(DeclensionRow { case, forms })
+            })
+            .collect::<Vec<DeclensionRow>>();
+        if columns.is_empty() || rows.is_empty() {
+            return None;
+        }
+        Some(Self { columns, rows })
+    }
+
+    fn table_rows(&self) -> Vec<Vec<String>> {
+        let mut rows = vec![[vec![String::new()], self.columns.clone()].concat()];
+        for row in &self.rows {
+            rows.push([vec![row.case.clone()], row.forms.clone()].concat());
+        }
+        rows
+    }
+
+    pub fn to_box_table(&self) -> String {
+        box_table(&self.table_rows())
+    }
+
+    pub fn to_html_table(&self) -> String {
+        html_table(&self.table_rows())
+    }
+}
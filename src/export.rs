@@ -0,0 +1,192 @@
+use crate::or::TranslationInfo;
+use crate::storage::Storage;
+use crate::table::escape_html;
+use crate::tags::tags;
+use clap::ValueEnum;
+use regex::Regex;
+use std::error::Error;
+use std::io::Write;
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum ExportFormat {
+    /// Pipe-delimited, the format read by `oraki import`.
+    Csv,
+    /// Every field, including declension, verb forms and sentences.
+    Json,
+    /// Word and translations, for Anki's text import or Quizlet.
+    Tsv,
+    /// A printable word list.
+    Md,
+    /// A printable word list.
+    Html,
+}
+
+// a real calendar date, sqlite would take `2020-13-45` and match nothing
+fn is_valid_date(date: &str) -> bool {
+    let date_re = Regex::new(r"^([0-9]{4})-([0-9]{2})-([0-9]{2})$").unwrap();
+    let Some(captures) = date_re.captures(date) else {
+        return false;
+    };
+    let [year, month, day] = [1, 2, 3].map(|i| captures[i].parse::<u32>().unwrap());
+    let is_leap_year = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+    let days_in_month = match month {
+        1 | 3 | 5 | 7 | 8 | 10 | 12 => 31,
+        4 | 6 | 9 | 11 => 30,
+        2 if is_leap_year => 29,
+        2 => 28,
+        _ => return false,
+    };
+    (1..=days_in_month).contains(&day)
+}
+
+// entries saved between `since` and `until`, both YYYY-MM-DD and inclusive, that have every tag
+pub fn filtered_entries(
+    storage: &Storage,
+    since: Option<&str>,
+    until: Option<&str>,
+    required_tags: &[String],
+) -> Result<Vec<TranslationInfo>, Box<dyn Error>> {
+    for date in since.iter().chain(until.iter()) {
+        if !is_valid_date(date) {
+            return Err(format!("`{date}` is not a valid YYYY-MM-DD date.").into());
+        }
+    }
+    Ok(storage
+        .saved_between(since, until)?
+        .into_iter()
+        .filter(|translation_info| {
+            let entry_tags = tags(translation_info);
+            required_tags.iter().all(|t| entry_tags.contains(t))
+        })
+        .collect())
+}
+
+// tabs and newlines would break the rows
+fn tsv_field(field: &str) -> String {
+    field.replace(['\t', '\n'], " ")
+}
+
+fn md_field(field: &str) -> String {
+    field.replace('|', "\\|").replace('\n', "<br>")
+}
+
+fn translations(translation_info: &TranslationInfo) -> String {
+    [translation_info.main_translation.clone()]
+        .into_iter()
+        .chain(translation_info.other_translations.iter().cloned())
+        .filter(|t| !t.is_empty())
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+fn example(translation_info: &TranslationInfo) -> (String, String) {
+    let phrases = translation_info.context_phrases().join(" / ");
+    let translation = translation_info
+        .context_phrase_translation
+        .clone()
+        .unwrap_or_default();
+    (phrases, translation)
+}
+
+fn write_tsv<W: Write>(
+    translation_infos: &[TranslationInfo],
+    mut writer: W,
+) -> Result<(), Box<dyn Error>> {
+    for translation_info in translation_infos {
+        writeln!(
+            writer,
+            "{}\t{}",
+            tsv_field(&translation_info.title),
+            tsv_field(&translations(translation_info))
+        )?;
+    }
+    Ok(())
+}
+
+fn write_md<W: Write>(
+    translation_infos: &[TranslationInfo],
+    mut writer: W,
+) -> Result<(), Box<dyn Error>> {
+    writeln!(writer, "# Vocabulary\n")?;
+    writeln!(writer, "| Word | Translation | Overview | Example |")?;
+    writeln!(writer, "|---|---|---|---|")?;
+    for translation_info in translation_infos {
+        let (phrases, translation) = example(translation_info);
+        let example = match translation.is_empty() {
+            true => phrases,
+            false => format!("{phrases}\n*{translation}*"),
+        };
+        writeln!(
+            writer,
+            "| **{}** | {} | {} | {} |",
+            md_field(&translation_info.title),
+            md_field(&translations(translation_info)),
+            md_field(&translation_info.overview_in_one_line()),
+            md_field(&example)
+        )?;
+    }
+    Ok(())
+}
+
+fn write_html<W: Write>(
+    translation_infos: &[TranslationInfo],
+    mut writer: W,
+) -> Result<(), Box<dyn Error>> {
+    writeln!(
+        writer,
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Vocabulary</title>\n\
+         <style>\nbody {{ font-family: sans-serif; }}\ntable {{ border-collapse: collapse; width: 100%; }}\n\
+         td, th {{ border: 1px solid #999; padding: 4px 8px; text-align: left; vertical-align: top; }}\n\
+         tr {{ page-break-inside: avoid; }}\n.example {{ color: #555; }}\n</style>\n</head>\n<body>\n\
+         <h1>Vocabulary</h1>\n<table>\n<tr><th>Word</th><th>Translation</th><th>Overview</th><th>Example</th></tr>"
+    )?;
+    for translation_info in translation_infos {
+        let (phrases, translation) = example(translation_info);
+        writeln!(
+            writer,
+            "<tr><td><b>{}</b></td><td>{}</td><td>{}</td><td>{}<br><span class=\"example\">{}</span></td></tr>",
+            escape_html(&translation_info.title),
+            escape_html(&translations(translation_info)),
+            escape_html(&translation_info.overview_in_one_line()),
+            escape_html(&phrases),
+            escape_html(&translation)
+        )?;
+    }
+    writeln!(writer, "</table>\n</body>\n</html>")?;
+    Ok(())
+}
+
+pub fn export<W: Write>(
+    translation_infos: &[TranslationInfo],
+    format: ExportFormat,
+    mut writer: W,
+) -> Result<(), Box<dyn Error>> {
+    match format {
+        ExportFormat::Csv => Storage::export_csv(translation_infos, writer),
+        ExportFormat::Json => {
+            serde_json::to_writer_pretty(&mut writer, translation_infos)?;
+            writeln!(writer)?;
+            Ok(())
+        }
+        ExportFormat::Tsv => write_tsv(translation_infos, writer),
+        ExportFormat::Md => write_md(translation_infos, writer),
+        ExportFormat::Html => write_html(translation_infos, writer),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dates_are_checked_against_the_calendar() {
+        assert!(is_valid_date("2024-02-29"));
+        assert!(is_valid_date("2020-12-31"));
+        assert!(!is_valid_date("2023-02-29"));
+        assert!(!is_valid_date("2020-13-45"));
+        assert!(!is_valid_date("2020-04-31"));
+        assert!(!is_valid_date("2020-00-10"));
+        assert!(!is_valid_date("2020-1-5"));
+        assert!(!is_valid_date("٢٠٢٤-٠١-٠١"));
+    }
+}
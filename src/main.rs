@@ -1,34 +1,98 @@
-use crate::anki::create_deck_from_csv;
-use std::env;
+use crate::anki::{create_deck_from_storage, CardType, SentenceChoice, SubDecks};
+use crate::ankiconnect::sync_storage;
+use crate::cli::{Cli, CollectionCommand, Command, ConfigCommand, DbCommand, PickArgs};
+use crate::config::Config;
+use crate::export::ExportFormat;
+use crate::or::OrError;
+use crate::output::SearchOutput;
+use crate::storage::Storage;
+use crate::templates::TemplateSet;
+use clap::{CommandFactory, Parser};
 use std::error::Error;
 use std::fs::File;
-use std::io::Write;
+use std::io::{self, Write};
+use std::path::Path;
+use std::process::ExitCode;
 
 mod anki;
+mod ankiconnect;
+mod browse;
+mod checkpoint;
+mod cli;
+mod cloze;
+mod collection;
+mod config;
+mod declension;
+mod export;
+mod offline;
 mod or;
+mod output;
+mod repl;
+mod review;
+mod scrape;
+mod storage;
+mod table;
+mod tags;
+mod templates;
 mod utils;
+mod verb;
 
-fn help() {
-    println!("Usage: oraki [option] [search_query]");
-    println!("-------------------------------------------------------------------");
-    println!("Options:");
-    println!("-c, --compile: Compile searched queries into $HOME/.local/share/oraki/output.apkg.");
-    println!("               Further arguments will be ignored.");
-    println!("-f, --file:    Do multiple searchs, one for each line of the file.");
-    println!();
-    println!("[search_query] can be both english or russian.");
-}
+// a search matched no word, told apart from other failures for scripts
+const EXIT_NO_RESULT: u8 = 3;
 
-async fn run(search_query: &str, print_full_info: bool) -> Result<bool, Box<dyn Error>> {
-    let (result_translation_info, already_existed) = or::get_translation_info(search_query).await?;
-    or::append_translation_info(&result_translation_info)?;
-    if print_full_info {
-        println!("{result_translation_info}")
-    } 
-    Ok(already_existed)
+// returns true if every saved result already existed
+async fn run(
+    fetcher: &or::Fetcher,
+    search_query: &str,
+    user_context_phrase: Option<&str>,
+    keep_both_phrases: bool,
+    pick: &or::Pick,
+    // None prints nothing
+    output: Option<SearchOutput>,
+    n_sentences: usize,
+) -> Result<bool, Box<dyn Error>> {
+    let mut results = or::get_translation_info(
+        fetcher,
+        search_query,
+        user_context_phrase,
+        keep_both_phrases,
+        pick,
+    )
+    .await?;
+    let mut all_existed = true;
+    for (result_translation_info, already_existed) in &mut results.translation_infos {
+        // a phrase given again for a saved word replaces the one it had
+        if let (true, Some(phrase)) = (*already_existed, user_context_phrase) {
+            if result_translation_info.set_user_context_phrase(phrase, keep_both_phrases) {
+                Storage::open()?.update(result_translation_info)?;
+            }
+        }
+        or::append_translation_info(result_translation_info)?;
+        if output == Some(SearchOutput::Pretty) {
+            println!("{result_translation_info}");
+            for sentence in result_translation_info.more_sentences(n_sentences) {
+                println!("\n{}\n{}", sentence.ru, sentence.en);
+            }
+        }
+        all_existed &= *already_existed;
+    }
+    // a saved word isn't looked up again, its candidates are still listed when they can be
+    if matches!(output, Some(SearchOutput::Json | SearchOutput::Plain))
+        && results.candidates.is_empty()
+    {
+        if let Ok(candidates) = or::find_candidates(fetcher, search_query).await {
+            results.candidates = candidates;
+        }
+    }
+    match output {
+        Some(SearchOutput::Json) => output::print_json(search_query, &results)?,
+        Some(SearchOutput::Plain) => output::print_plain(&results),
+        _ => {}
+    }
+    Ok(all_existed)
 }
 
-fn vec_to_file(filename: &str, lines: &Vec<String>) -> Result<(), Box<dyn Error>> {
+fn vec_to_file(filename: &Path, lines: &Vec<String>) -> Result<(), Box<dyn Error>> {
     let mut file = File::create(filename)?;
     for line in lines {
         file.write_all(line.as_bytes())?;
@@ -37,44 +101,300 @@ fn vec_to_file(filename: &str, lines: &Vec<String>) -> Result<(), Box<dyn Error>
     Ok(())
 }
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn Error>> {
-    let args: Vec<String> = env::args().collect();
-    match args.len() {
-        1 => {
-            help();
-            return Ok(());
-        }
-        2 => {
-            if ["-c", "--compile"].contains(&args[1].as_str()) {
-                create_deck_from_csv()?;
-                return Ok(());
+struct BatchOptions {
+    keep_both: bool,
+    jobs: usize,
+    max_rps: f64,
+    resume: bool,
+    retry_failed: bool,
+    source: Option<String>,
+    pick: or::Pick,
+}
+
+async fn batch(
+    file: Option<&Path>,
+    failed_output: &Path,
+    options: BatchOptions,
+) -> Result<(), Box<dyn Error>> {
+    let file = match (file, options.retry_failed) {
+        (_, true) => failed_output,
+        (Some(file), false) => file,
+        (None, false) => return Err("Missing file argument.".into()),
+    };
+    let fetcher = or::Fetcher::new(Some(options.max_rps));
+    let results = or::append_translation_infos_from_file_name(
+        &fetcher,
+        file,
+        options.keep_both,
+        options.jobs,
+        options.retry_failed,
+        options.resume,
+        &options.pick,
+    )
+    .await?;
+    println!();
+    println!("========");
+    println!();
+    println!("Results:\n  Fetched: {}/{}\n  Had: {}/{}\n  Failed: {}/{}",
+        results.fetched_results.len(),
+        results.n_total,
+        results.existent_results.len(),
+        results.n_total,
+        results.failed_results.len(),
+        results.n_total,
+        );
+    let failed_lines = results
+        .failed_results
+        .iter()
+        .map(|(search_query, reason)| format!("{search_query}\t{reason}"))
+        .collect();
+    vec_to_file(failed_output, &failed_lines)?;
+    if let Some(source) = &options.source {
+        let saved_queries = [results.fetched_results, results.existent_results].concat();
+        Storage::open()?.set_source(&saved_queries, source)?;
+    }
+    println!();
+    println!("Failed results written to `{}`.", failed_output.display());
+    Ok(())
+}
+
+async fn sync(
+    url: &str,
+    sentence_choice: SentenceChoice,
+    card_types: &[CardType],
+    sub_decks: Option<SubDecks>,
+    templates: &TemplateSet,
+) -> Result<(), Box<dyn Error>> {
+    let result = sync_storage(url, sentence_choice, card_types, sub_decks, templates).await?;
+    for title in &result.added {
+        println!("Added {title}.");
+    }
+    for title in &result.updated {
+        println!("Updated {title}.");
+    }
+    println!(
+        "\nAdded: {}\nUpdated: {}\nUnchanged: {}",
+        result.added.len(),
+        result.updated.len(),
+        result.unchanged.len()
+    );
+    Ok(())
+}
+
+fn list() -> Result<(), Box<dyn Error>> {
+    for translation_info in Storage::open()?.all()? {
+        println!(
+            "{} ({}): {}",
+            translation_info.title,
+            translation_info.search_query,
+            translation_info.main_translation
+        );
+    }
+    Ok(())
+}
+
+fn remove(search_query: &str) -> Result<(), Box<dyn Error>> {
+    if !Storage::open()?.remove(search_query)? {
+        return Err(format!("No saved search for `{search_query}`.").into());
+    }
+    println!("Removed `{search_query}`.");
+    Ok(())
+}
+
+fn export(
+    output: Option<&Path>,
+    format: ExportFormat,
+    tags: &[String],
+    since: Option<&str>,
+    until: Option<&str>,
+) -> Result<(), Box<dyn Error>> {
+    let translation_infos = export::filtered_entries(&Storage::open()?, since, until, tags)?;
+    match output {
+        Some(p) => export::export(&translation_infos, format, File::create(p)?),
+        None => export::export(&translation_infos, format, io::stdout()),
+    }
+}
+
+fn collection(command: CollectionCommand) -> Result<(), Box<dyn Error>> {
+    let config = config::get();
+    let active = config.collection.as_deref().unwrap_or(collection::DEFAULT_COLLECTION);
+    match command {
+        CollectionCommand::List => {
+            for name in collection::names(&config.data_dir)? {
+                let db_path = collection::collection_dir(&config.data_dir, &name).join("main.db");
+                let n_entries = Storage::open_path(&db_path)?.count()?;
+                let marker = if name == active { "*" } else { " " };
+                println!("{marker} {name} ({n_entries} saved searches)");
             }
-            if ["-f", "--file"].contains(&args[1].as_str()) {
-                panic!("Missing file argument.");
+        }
+        CollectionCommand::Create { name, deck_name } => {
+            collection::create(config, &name, deck_name.as_deref())?;
+            println!("Created collection `{name}`, use it with `--collection {name}`.");
+        }
+        CollectionCommand::Merge {
+            source,
+            destination,
+        } => {
+            let n_merged = collection::merge(&config.data_dir, &source, &destination)?;
+            println!("Copied {n_merged} new entries from `{source}` into `{destination}`.");
+        }
+        CollectionCommand::Delete { name, yes } => {
+            // nothing to confirm for a name that isn't a collection
+            collection::check_exists(&config.data_dir, &name)?;
+            if !yes {
+                print!("Delete collection `{name}` and everything saved in it? [y/N] ");
+                io::stdout().flush()?;
+                let mut answer = String::new();
+                io::stdin().read_line(&mut answer)?;
+                if !answer.trim().eq_ignore_ascii_case("y") {
+                    return Ok(());
+                }
             }
+            collection::delete(&config.data_dir, &name)?;
+            println!("Deleted collection `{name}`.");
         }
-        _ => {
-            if ["-f", "--file"].contains(&args[1].as_str()) {
-                let results = or::append_translation_infos_from_file_name(&args[2]).await?;
-                println!();
-                println!("========");
-                println!();
-                println!("Results:\n  Fetched: {}/{}\n  Had: {}/{}\n  Failed: {}/{}",
-                    results.fetched_results.len(),
-                    results.n_total,
-                    results.existent_results.len(),
-                    results.n_total,
-                    results.failed_results.len(),
-                    results.n_total,
-                    );
-                vec_to_file("failed.out", &results.failed_results)?;
-                println!();
-                println!("Failed results written to `failed.out`.");
-                return Ok(());
+    }
+    Ok(())
+}
+
+// card types given on the command line replace the ones in the config
+fn cards_or_default(cards: Vec<CardType>) -> Vec<CardType> {
+    match cards.is_empty() {
+        true => config::get().cards.clone(),
+        false => cards,
+    }
+}
+
+async fn dispatch(cli: Cli) -> Result<(), Box<dyn Error>> {
+    let mut config = Config::load(cli.config.as_deref())?;
+    if let Some(name) = cli.collection.or(config.collection.clone()) {
+        config.use_collection(&name)?;
+    }
+    config.offline = (config.offline || cli.offline) && !cli.online;
+    config::init(config);
+    let config_path = cli.config;
+    let command = match cli.command {
+        Some(command) => command,
+        None if cli.query.is_empty() => Command::Repl,
+        None => Command::Search {
+            query: cli.query,
+            sentences: 1,
+            interactive: false,
+            output: SearchOutput::Pretty,
+            pick: PickArgs {
+                pick: None,
+                all: false,
+            },
+        },
+    };
+    match command {
+        Command::Search {
+            query,
+            sentences,
+            interactive,
+            output,
+            pick,
+        } => {
+            let fetcher = or::Fetcher::new(None);
+            let pick = pick.to_pick(interactive);
+            run(&fetcher, &query.join(" "), None, false, &pick, Some(output), sentences).await?;
+        }
+        Command::Repl => repl::repl().await?,
+        Command::Batch {
+            file,
+            keep_both,
+            failed_output,
+            jobs,
+            max_rps,
+            resume,
+            retry_failed,
+            source,
+            pick,
+        } => {
+            let options = BatchOptions {
+                keep_both,
+                jobs: jobs.unwrap_or(config::get().jobs),
+                max_rps: max_rps.unwrap_or(config::get().max_rps),
+                resume,
+                retry_failed,
+                source,
+                pick: pick.to_pick(false),
+            };
+            batch(file.as_deref(), &failed_output, options).await?
+        }
+        Command::Compile {
+            sentence,
+            cards,
+            cloze,
+            subdecks,
+            templates,
+        } => {
+            let templates = TemplateSet::load(templates.as_deref())?;
+            let cards = cards_or_default(cards);
+            create_deck_from_storage(sentence, &cards, cloze, subdecks, &templates)?
+        }
+        Command::Sync {
+            url,
+            sentence,
+            cards,
+            subdecks,
+            templates,
+        } => {
+            let templates = TemplateSet::load(templates.as_deref())?;
+            let cards = cards_or_default(cards);
+            sync(&url, sentence, &cards, subdecks, &templates).await?
+        }
+        Command::List => list()?,
+        Command::Browse => browse::browse().await?,
+        Command::Review {
+            direction,
+            limit,
+            new,
+        } => review::review(direction, limit, new)?,
+        Command::Remove { query } => remove(&query.join(" "))?,
+        Command::Import { file } => {
+            let n_imported = Storage::open()?.import_csv(&file)?;
+            println!("Imported {n_imported} new entries from {}.", file.display());
+        }
+        Command::Export {
+            output,
+            format,
+            tag,
+            since,
+            until,
+        } => export(output.as_deref(), format, &tag, since.as_deref(), until.as_deref())?,
+        Command::Completions { shell } => {
+            clap_complete::generate(shell, &mut Cli::command(), "oraki", &mut io::stdout());
+        }
+        Command::Collection { command } => collection(command)?,
+        Command::Db {
+            command: DbCommand::Import { dir },
+        } => {
+            for (table, n_rows) in offline::import_dumps(&dir)? {
+                println!("Imported {n_rows} rows into {table}.");
             }
         }
+        Command::Config {
+            command: ConfigCommand::Show,
+        } => {
+            let config_path = config_path.unwrap_or_else(config::default_config_path);
+            println!("# {}", config_path.display());
+            print!("{}", config::get().to_toml()?);
+        }
     };
-    run(args[1].as_str(), true).await?;
     Ok(())
 }
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    match dispatch(Cli::parse()).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("error: {error}");
+            match error.downcast_ref::<OrError>() {
+                Some(OrError::NoSuggestion(_)) => ExitCode::from(EXIT_NO_RESULT),
+                _ => ExitCode::FAILURE,
+            }
+        }
+    }
+}
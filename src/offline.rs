@@ -0,0 +1,587 @@
+use crate::anki::note_guid;
+use crate::config;
+use crate::declension::{Declension, DeclensionRow};
+use crate::or::{Candidate, OrError, Sentence, TranslationInfo};
+use crate::utils::get_offline_db_path;
+use crate::verb::{Verb, VerbForm};
+use csv::ReaderBuilder;
+use rusqlite::{params_from_iter, Connection, OptionalExtension, Row};
+use std::error::Error;
+use std::path::Path;
+
+// tables of the OpenRussian dump that are read, the first two are required
+const TABLES: [&str; 11] = [
+    "words",
+    "translations",
+    "words_forms",
+    "sentences",
+    "sentences_translations",
+    "sentences_words",
+    "declensions",
+    "nouns",
+    "adjectives",
+    "verbs",
+    "conjugations",
+];
+// indexed after the import, when the table has the column
+const INDEXES: [(&str, &str); 8] = [
+    ("words", "bare"),
+    ("words", "accented"),
+    ("words", "id"),
+    ("translations", "word_id"),
+    ("words_forms", "form_bare"),
+    ("sentences_words", "word_id"),
+    ("sentences_translations", "sentence_id"),
+    ("declensions", "id"),
+];
+const CASES: [&str; 6] = ["Nominative", "Genitive", "Dative", "Accusative", "Instrumental", "Prepositional"];
+const CASE_COLUMNS: [&str; 6] = ["nom", "gen", "dat", "acc", "inst", "prep"];
+const PERSONS: [&str; 6] = ["я", "ты", "он/она/оно", "мы", "вы", "они"];
+const PERSON_COLUMNS: [&str; 6] = ["sg1", "sg2", "sg3", "pl1", "pl2", "pl3"];
+const MAX_SENTENCES: usize = 20;
+
+// the dumps mark stress with an apostrophe after the vowel, the pages with U+0301
+fn stressed(text: &str) -> String {
+    text.replace('\'', "\u{301}")
+}
+
+// several forms in one cell are shown the same way as on the pages
+fn forms(text: &str) -> String {
+    text.split(',')
+        .map(|f| stressed(f.trim()))
+        .filter(|f| !f.is_empty())
+        .collect::<Vec<String>>()
+        .join(" / ")
+}
+
+fn quote(identifier: &str) -> String {
+    format!("\"{}\"", identifier.replace('"', "\"\""))
+}
+
+fn import_error<E: std::fmt::Display>(error: E) -> OrError {
+    OrError::OfflineIndex(error.to_string())
+}
+
+// both tab and comma separated dumps are around
+fn import_table(conn: &Connection, table: &str, csv_path: &Path) -> Result<usize, Box<dyn Error>> {
+    let first_line = std::fs::read_to_string(csv_path)?
+        .lines()
+        .next()
+        .unwrap_or_default()
+        .to_string();
+    let delimiter = match first_line.matches('\t').count() >= first_line.matches(',').count() {
+        true => b'\t',
+        false => b',',
+    };
+    let mut reader = ReaderBuilder::new()
+        .delimiter(delimiter)
+        .flexible(true)
+        .from_path(csv_path)?;
+    let columns = reader
+        .headers()?
+        .iter()
+        .map(|c| c.trim().to_string())
+        .collect::<Vec<String>>();
+    conn.execute_batch(&format!(
+        "DROP TABLE IF EXISTS {0}; CREATE TABLE {0} ({1});",
+        quote(table),
+        columns.iter().map(|c| quote(c)).collect::<Vec<String>>().join(", ")
+    ))?;
+    let mut statement = conn.prepare(&format!(
+        "INSERT INTO {} VALUES ({})",
+        quote(table),
+        vec!["?"; columns.len()].join(", ")
+    ))?;
+    let mut n_rows = 0;
+    for record in reader.records() {
+        let mut values = record?.iter().map(String::from).collect::<Vec<String>>();
+        values.resize(columns.len(), String::new());
+        statement.execute(params_from_iter(values))?;
+        n_rows += 1;
+    }
+    Ok(n_rows)
+}
+
+// loads every known `<table>.csv` in `dir` into the offline index, replacing what was there
+pub fn import_dumps(dir: &Path) -> Result<Vec<(&'static str, usize)>, Box<dyn Error>> {
+    for required in &TABLES[..2] {
+        if !dir.join(format!("{required}.csv")).is_file() {
+            return Err(format!("Missing {required}.csv in {}.", dir.display()).into());
+        }
+    }
+    let mut conn = Connection::open(get_offline_db_path()?)?;
+    import_dumps_into(&mut conn, dir)
+}
+
+fn import_dumps_into(
+    conn: &mut Connection,
+    dir: &Path,
+) -> Result<Vec<(&'static str, usize)>, Box<dyn Error>> {
+    let tx = conn.transaction()?;
+    let mut imported = vec![];
+    for table in TABLES {
+        let csv_path = dir.join(format!("{table}.csv"));
+        if csv_path.is_file() {
+            imported.push((table, import_table(&tx, table, &csv_path)?));
+        }
+    }
+    for (table, column) in INDEXES {
+        if has_column(&tx, table, column)? {
+            tx.execute_batch(&format!(
+                "CREATE INDEX IF NOT EXISTS {} ON {} ({})",
+                quote(&format!("{table}_{column}")),
+                quote(table),
+                quote(column)
+            ))?;
+        }
+    }
+    tx.commit()?;
+    Ok(imported)
+}
+
+fn has_column(conn: &Connection, table: &str, column: &str) -> rusqlite::Result<bool> {
+    let mut statement = conn.prepare(&format!("PRAGMA table_info({})", quote(table)))?;
+    let columns = statement
+        .query_map([], |row| row.get::<_, String>(1))?
+        .collect::<rusqlite::Result<Vec<String>>>()?;
+    Ok(columns.iter().any(|c| c == column))
+}
+
+struct Word {
+    id: String,
+    accented: String,
+    word_type: String,
+    rank: Option<usize>,
+    audio: String,
+}
+
+impl Word {
+    const COLUMNS: &'static str = "words.id, words.accented, words.type, words.rank, words.audio";
+
+    fn from_row(row: &Row) -> rusqlite::Result<Self> {
+        Ok(Self {
+            id: row.get(0)?,
+            accented: row.get(1)?,
+            word_type: row.get(2)?,
+            rank: row.get::<_, String>(3)?.parse().ok(),
+            audio: row.get(4)?,
+        })
+    }
+}
+
+// the index built by `oraki db import`, used instead of OpenRussian with `--offline`
+pub struct OfflineIndex {
+    conn: Connection,
+}
+
+impl OfflineIndex {
+    pub fn open() -> Result<Self, OrError> {
+        let db_path = get_offline_db_path().map_err(import_error)?;
+        if !db_path.is_file() {
+            return Err(OrError::OfflineIndex(String::from(
+                "not imported yet, run `oraki db import <dir>` first",
+            )));
+        }
+        let conn = Connection::open(db_path).map_err(import_error)?;
+        Ok(Self { conn })
+    }
+
+    fn has_table(&self, table: &str) -> bool {
+        has_column(&self.conn, table, "id").unwrap_or(false)
+            || has_column(&self.conn, table, "word_id").unwrap_or(false)
+    }
+
+    // most used words first, words without rank last
+    fn words(&self, condition: &str, params: &[&str]) -> Result<Vec<Word>, OrError> {
+        // not every dump marks disabled words
+        let enabled = match has_column(&self.conn, "words", "disabled").map_err(import_error)? {
+            true => "AND words.disabled IN ('', '0')",
+            false => "",
+        };
+        let mut statement = self
+            .conn
+            .prepare(&format!(
+                "SELECT DISTINCT {} FROM words {condition} {enabled} \
+                 ORDER BY words.rank = '', CAST(words.rank AS INTEGER), words.id",
+                Word::COLUMNS
+            ))
+            .map_err(import_error)?;
+        let words = statement
+            .query_map(params_from_iter(params), Word::from_row)
+            .map_err(import_error)?
+            .collect::<rusqlite::Result<Vec<Word>>>()
+            .map_err(import_error)?;
+        Ok(words)
+    }
+
+    fn translations(&self, word_id: &str) -> Result<Vec<String>, OrError> {
+        let mut statement = self
+            .conn
+            .prepare(
+                "SELECT tl FROM translations WHERE word_id = ? AND lang = ? \
+                 ORDER BY CAST(position AS INTEGER)",
+            )
+            .map_err(import_error)?;
+        let translations = statement
+            .query_map([word_id, &config::get().language], |row| row.get::<_, String>(0))
+            .map_err(import_error)?
+            .collect::<rusqlite::Result<Vec<String>>>()
+            .map_err(import_error)?;
+        Ok(translations
+            .iter()
+            .flat_map(|tl| tl.split(", "))
+            .map(|tl| tl.trim().to_string())
+            .filter(|tl| !tl.is_empty())
+            .collect())
+    }
+
+    // russian queries match the word or any of its forms, others match a translation
+    pub fn get_candidates(&self, search_query: &str) -> Result<Vec<Candidate>, OrError> {
+        let query = search_query
+            .trim()
+            .to_lowercase()
+            .replace(['\'', '\u{301}'], "");
+        let is_russian = query.chars().any(|c| matches!(c, 'а'..='я' | 'ё'));
+        let words = match is_russian {
+            true if self.has_table("words_forms") => self.words(
+                "WHERE (words.bare = ?1 OR words.id IN \
+                 (SELECT word_id FROM words_forms WHERE form_bare = ?1))",
+                &[&query],
+            )?,
+            true => self.words("WHERE words.bare = ?1", &[&query])?,
+            false => self.words(
+                "JOIN translations ON translations.word_id = words.id \
+                 WHERE translations.lang = ?2 AND (translations.tl LIKE ?1 \
+                 OR translations.tl LIKE ?1 || ',%' OR translations.tl LIKE '%, ' || ?1 \
+                 OR translations.tl LIKE '%, ' || ?1 || ',%')",
+                &[&query, &config::get().language],
+            )?,
+        };
+        let mut candidates = vec![];
+        for word in words {
+            let translations = self.translations(&word.id)?;
+            if translations.is_empty() {
+                continue;
+            }
+            candidates.push(Candidate {
+                ru: word.accented,
+                translations,
+                part_of_speech: Some(word.word_type).filter(|t| !t.is_empty()),
+            });
+        }
+        if candidates.is_empty() {
+            return Err(OrError::NoSuggestion(String::from(search_query)));
+        }
+        Ok(candidates)
+    }
+
+    fn row_values(&self, table: &str, key: &str, value: &str, columns: &[&str]) -> Option<Vec<String>> {
+        if value.is_empty() || !self.has_table(table) {
+            return None;
+        }
+        let sql = format!(
+            "SELECT {} FROM {} WHERE {} = ?",
+            columns.iter().map(|c| quote(c)).collect::<Vec<String>>().join(", "),
+            quote(table),
+            quote(key)
+        );
+        self.conn
+            .query_row(&sql, [value], |row| {
+                (0..columns.len()).map(|i| row.get::<_, String>(i)).collect()
+            })
+            .optional()
+            .ok()
+            .flatten()
+    }
+
+    fn declension_forms(&self, declension_id: &str) -> Option<Vec<String>> {
+        self.row_values("declensions", "id", declension_id, &CASE_COLUMNS)
+            .map(|forms| forms.iter().map(|f| self::forms(f)).collect())
+    }
+
+    fn declension(&self, word: &Word) -> Option<Declension> {
+        let (columns, declension_ids) = match word.word_type.as_str() {
+            "noun" => (
+                vec!["singular", "plural"],
+                self.row_values("nouns", "word_id", &word.id, &["decl_sg_id", "decl_pl_id"])?,
+            ),
+            "adjective" => (
+                vec!["masculine", "feminine", "neuter", "plural"],
+                self.row_values(
+                    "adjectives",
+                    "word_id",
+                    &word.id,
+                    &["decl_m_id", "decl_f_id", "decl_n_id", "decl_pl_id"],
+                )?,
+            ),
+            _ => return None,
+        };
+        let tables = declension_ids
+            .iter()
+            .map(|id| self.declension_forms(id).unwrap_or_else(|| vec![String::new(); CASES.len()]))
+            .collect::<Vec<Vec<String>>>();
+        if tables.iter().flatten().all(|f| f.is_empty()) {
+            return None;
+        }
+        let mut rows = CASES
+            .iter()
+            .enumerate()
+            .map(|(i, case)| DeclensionRow {
+                case: case.to_string(),
+                forms: tables.iter().map(|t| t[i].clone()).collect(),
+            })
+            .collect::<Vec<DeclensionRow>>();
+        if word.word_type == "adjective" {
+            let short = self.row_values(
+                "adjectives",
+                "word_id",
+                &word.id,
+                &["short_m", "short_f", "short_n", "short_pl"],
+            );
+            if let Some(short) = short.filter(|s| s.iter().any(|f| !f.is_empty())) {
+                rows.push(DeclensionRow {
+                    case: String::from("Short"),
+                    forms: short.iter().map(|f| forms(f)).collect(),
+                });
+            }
+        }
+        Some(Declension {
+            columns: columns.into_iter().map(String::from).collect(),
+            rows,
+        })
+    }
+
+    fn verb(&self, word: &Word) -> Option<Verb> {
+        if word.word_type != "verb" {
+            return None;
+        }
+        let columns = [
+            "aspect",
+            "partner",
+            "imperative_sg",
+            "imperative_pl",
+            "past_m",
+            "past_f",
+            "past_n",
+            "past_pl",
+            "presfut_conj_id",
+        ];
+        let values = self.row_values("verbs", "word_id", &word.id, &columns)?;
+        let labeled = |labels: &[&str], forms: &[String]| {
+            labels
+                .iter()
+                .zip(forms)
+                .filter(|(_, form)| !form.is_empty())
+                .map(|(label, form)| VerbForm {
+                    label: label.to_string(),
+                    form: self::forms(form),
+                })
+                .collect::<Vec<VerbForm>>()
+        };
+        let conjugation = self
+            .row_values("conjugations", "id", &values[8], &PERSON_COLUMNS)
+            .map(|forms| labeled(&PERSONS, &forms))
+            .unwrap_or_default();
+        Some(Verb {
+            aspect: Some(values[0].clone()).filter(|a| !a.is_empty()),
+            aspect_partner: values[1]
+                .split(',')
+                .map(|p| stressed(p.trim()))
+                .find(|p| !p.is_empty()),
+            conjugation,
+            past: labeled(&["masculine", "feminine", "neuter", "plural"], &values[4..8]),
+            imperative: labeled(&["singular", "plural"], &values[2..4]),
+        })
+    }
+
+    fn sentences(&self, word_id: &str) -> Result<Vec<Sentence>, OrError> {
+        if !self.has_table("sentences_words") || !self.has_table("sentences_translations") {
+            return Ok(vec![]);
+        }
+        let mut statement = self
+            .conn
+            .prepare(
+                "SELECT sentences.ru, sentences_translations.tl FROM sentences_words \
+                 JOIN sentences ON sentences.id = sentences_words.sentence_id \
+                 JOIN sentences_translations ON sentences_translations.sentence_id = sentences.id \
+                 WHERE sentences_words.word_id = ? AND sentences_translations.lang = ? \
+                 GROUP BY sentences.id ORDER BY sentences.id LIMIT ?",
+            )
+            .map_err(import_error)?;
+        let sentences = statement
+            .query_map(
+                rusqlite::params![word_id, config::get().language, MAX_SENTENCES],
+                |row| {
+                    Ok(Sentence {
+                        ru: stressed(&row.get::<_, String>(0)?),
+                        en: row.get(1)?,
+                    })
+                },
+            )
+            .map_err(import_error)?
+            .collect::<rusqlite::Result<Vec<Sentence>>>()
+            .map_err(import_error)?;
+        Ok(sentences)
+    }
+
+    fn overview(&self, word: &Word) -> String {
+        let mut description = vec![word.word_type.clone()];
+        if word.word_type == "noun" {
+            let noun = self.row_values(
+                "nouns",
+                "word_id",
+                &word.id,
+                &["gender", "animate", "indeclinable", "sg_only", "pl_only"],
+            );
+            if let Some(noun) = noun {
+                let gender = match noun[0].as_str() {
+                    "m" => "masculine",
+                    "f" => "feminine",
+                    "n" => "neuter",
+                    _ => "",
+                };
+                description.push(gender.to_string());
+                description.push(String::from(match noun[1].as_str() {
+                    "1" => "animate",
+                    _ => "inanimate",
+                }));
+                for (flag, name) in noun[2..].iter().zip(["indeclinable", "singular only", "plural only"]) {
+                    if flag == "1" {
+                        description.push(name.to_string());
+                    }
+                }
+            }
+        }
+        if let Some(aspect) = self.verb(word).and_then(|v| v.aspect) {
+            description.push(aspect);
+        }
+        description.retain(|d| !d.is_empty());
+        let mut overview = description.join(", ");
+        if let Some(rank) = word.rank {
+            let band = rank.div_ceil(1000) * 1000;
+            let band = match band >= 1000 {
+                true => format!("{},{:03}", band / 1000, band % 1000),
+                false => band.to_string(),
+            };
+            overview.push_str(&format!("\ntop {band}"));
+        }
+        overview
+    }
+
+    // `candidate` is one returned by `get_candidates`
+    pub fn get_translation_info(
+        &self,
+        search_query: &str,
+        candidate: &Candidate,
+        user_context_phrase: Option<&str>,
+        keep_both_phrases: bool,
+    ) -> Result<TranslationInfo, OrError> {
+        let word = self
+            .words("WHERE words.accented = ?1", &[&candidate.ru])?
+            .into_iter()
+            .next()
+            .ok_or(OrError::MissingField("title"))?;
+        let search_result = word.accented.replace('\'', "");
+        let mut translations = self.translations(&word.id)?.into_iter();
+        let main_translation = translations
+            .next()
+            .ok_or(OrError::MissingField("main_translation"))?;
+
+        let sentences = self.sentences(&word.id)?;
+        let (context_phrase, context_phrase_translation) = match sentences.first() {
+            Some(first_sentence) if user_context_phrase.is_none() || keep_both_phrases => {
+                (Some(first_sentence.ru.clone()), Some(first_sentence.en.clone()))
+            }
+            _ => (None, None),
+        };
+        Ok(TranslationInfo {
+            search_query: String::from(search_query),
+            guid: note_guid(&search_result),
+            search_result,
+            title: stressed(&word.accented),
+            main_translation,
+            other_translations: translations.collect(),
+            overview: self.overview(&word),
+            context_phrase,
+            context_phrase_translation,
+            user_context_phrase: user_context_phrase.map(String::from),
+            declension: self.declension(&word),
+            verb: self.verb(&word),
+            sentences,
+            audio_url: Some(word.audio).filter(|a| a.starts_with("http")),
+            audio_file: None,
+            source: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a few rows in the format of the OpenRussian dump
+    fn fixture_index() -> OfflineIndex {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/offline");
+        let mut conn = Connection::open_in_memory().unwrap();
+        let imported = import_dumps_into(&mut conn, &dir).unwrap();
+        assert_eq!(
+            imported,
+            [("words", 3), ("translations", 4), ("declensions", 2), ("nouns", 1)]
+        );
+        OfflineIndex { conn }
+    }
+
+    #[test]
+    fn russian_queries_find_the_word() {
+        let candidates = fixture_index().get_candidates("Нож").unwrap();
+        assert_eq!(candidates.len(), 1);
+        assert_eq!(candidates[0].ru, "но'ж");
+        assert_eq!(candidates[0].translations, ["knife", "table-knife"]);
+        assert_eq!(candidates[0].part_of_speech.as_deref(), Some("noun"));
+    }
+
+    #[test]
+    fn english_queries_find_the_words_translated_so() {
+        let candidates = fixture_index().get_candidates("knife").unwrap();
+        let words = candidates.iter().map(|c| c.ru.as_str()).collect::<Vec<&str>>();
+        // ranked first, the disabled word is left out
+        assert_eq!(words, ["но'ж", "но'жик"]);
+        assert!(matches!(
+            fixture_index().get_candidates("fork"),
+            Err(OrError::NoSuggestion(_))
+        ));
+    }
+
+    #[test]
+    fn translation_info_comes_from_the_index() {
+        let index = fixture_index();
+        let candidate = &index.get_candidates("нож").unwrap()[0];
+        let translation_info = index.get_translation_info("нож", candidate, None, false).unwrap();
+        assert_eq!(translation_info.search_result, "нож");
+        assert_eq!(translation_info.title, "но\u{301}ж");
+        assert_eq!(translation_info.main_translation, "knife");
+        assert_eq!(translation_info.other_translations, ["table-knife"]);
+        assert_eq!(translation_info.overview, "noun, masculine, inanimate\ntop 2,000");
+        assert_eq!(translation_info.guid, note_guid("нож"));
+        let declension = translation_info.declension.unwrap();
+        assert_eq!(declension.columns, ["singular", "plural"]);
+        assert_eq!(declension.rows[0].forms, ["но\u{301}ж", "ножи\u{301}"]);
+        assert_eq!(declension.rows[4].forms, ["ножо\u{301}м", "ножа\u{301}ми"]);
+        assert!(translation_info.verb.is_none());
+        assert_eq!(translation_info.audio_url.as_deref(), Some("https://example.org/nozh.mp3"));
+    }
+
+    #[test]
+    fn dumps_without_a_disabled_column_are_read() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE words (id, bare, accented, type, rank, audio);
+             INSERT INTO words VALUES ('1', 'нож', 'но''ж', 'noun', '', '');
+             CREATE TABLE translations (word_id, lang, position, tl);
+             INSERT INTO translations VALUES ('1', 'en', '0', 'knife');",
+        )
+        .unwrap();
+        let candidates = OfflineIndex { conn }.get_candidates("нож").unwrap();
+        assert_eq!(candidates[0].translations, ["knife"]);
+    }
+}
@@ -1,28 +1,146 @@
-use crate::utils::get_main_csv_path;
+use crate::anki::note_guid;
+use crate::checkpoint::{Checkpoint, CheckpointEntry, Outcome};
+use crate::config;
+use crate::declension::Declension;
+use crate::offline::OfflineIndex;
+use crate::storage::Storage;
+use crate::utils::get_media_dir;
+use crate::verb::Verb;
+use futures::future;
+use futures::stream::{self, StreamExt};
 use indicatif::{ProgressBar, ProgressStyle};
-use csv::{ReaderBuilder, WriterBuilder};
 use regex::Regex;
 use reqwest::header::USER_AGENT;
+use reqwest::{Response, StatusCode, Url};
 use scraper::Html;
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use std::collections::HashSet;
 use std::error::Error;
 use std::fmt;
-use std::fs::OpenOptions;
-use std::io::{BufRead, BufReader};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tokio::time::{self, Interval, MissedTickBehavior};
 
-const HEADER_USER_AGENT : &str= "Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/86.0.4240.75 Safari/537.36";
+pub const HEADER_USER_AGENT : &str= "Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/86.0.4240.75 Safari/537.36";
 const DEFAULT_EMPTY_VALUE: &str = "-";
+const MAX_RETRIES: u32 = 3;
+const RETRY_BASE_DELAY: Duration = Duration::from_millis(500);
 
-#[derive(Debug, serde::Deserialize)]
+#[derive(Debug)]
+pub enum OrError {
+    Network(reqwest::Error),
+    HttpStatus(StatusCode),
+    NoSuggestion(String),
+    LayoutChanged(String),
+    MissingField(&'static str),
+    OfflineIndex(String),
+}
+
+impl OrError {
+    // worth trying again later, the word itself is not the problem
+    pub fn is_transient(&self) -> bool {
+        match self {
+            OrError::Network(_) => true,
+            OrError::HttpStatus(status) => {
+                status.is_server_error() || *status == StatusCode::TOO_MANY_REQUESTS
+            }
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for OrError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OrError::Network(error) => write!(f, "network error: {error}"),
+            OrError::HttpStatus(status) => write!(f, "http status {status}"),
+            OrError::NoSuggestion(search_query) => {
+                write!(f, "no suggestion found for `{search_query}`")
+            }
+            OrError::LayoutChanged(what) => write!(f, "page layout changed: {what}"),
+            OrError::MissingField(field) => write!(f, "missing field `{field}`"),
+            OrError::OfflineIndex(what) => write!(f, "offline index: {what}"),
+        }
+    }
+}
+
+impl Error for OrError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            OrError::Network(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for OrError {
+    fn from(error: reqwest::Error) -> Self {
+        match error.status() {
+            Some(status) => OrError::HttpStatus(status),
+            None => OrError::Network(error),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
 pub struct TranslationInfo {
-    search_query: String,
-    search_result: String,
-    context_phrase: Option<String>,
-    context_phrase_translation: Option<String>,
-    title: String,
-    main_translation: String,
-    other_translations: Vec<String>,
-    overview: String,
+    pub search_query: String,
+    pub search_result: String,
+    pub context_phrase: Option<String>,
+    pub context_phrase_translation: Option<String>,
+    pub user_context_phrase: Option<String>,
+    pub title: String,
+    pub main_translation: String,
+    pub other_translations: Vec<String>,
+    pub overview: String,
+    pub declension: Option<Declension>,
+    pub verb: Option<Verb>,
+    // every example sentence on the page, the first one is also the context phrase
+    pub sentences: Vec<Sentence>,
+    pub audio_url: Option<String>,
+    // name of the downloaded file inside the media dir
+    pub audio_file: Option<String>,
+    // anki note id, kept the same when the entry is fetched again
+    #[serde(default)]
+    pub guid: String,
+    // name given to the batch import the entry came from
+    #[serde(default)]
+    pub source: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Sentence {
+    pub ru: String,
+    pub en: String,
+}
+
+// an entry with only the word and its translation, searched by its unstressed form
+#[cfg(test)]
+impl TranslationInfo {
+    pub fn for_test(title: &str, main_translation: &str) -> Self {
+        let search_result = title.replace('\u{301}', "");
+        Self {
+            search_query: search_result.clone(),
+            guid: note_guid(&search_result),
+            search_result,
+            context_phrase: None,
+            context_phrase_translation: None,
+            user_context_phrase: None,
+            title: title.to_string(),
+            main_translation: main_translation.to_string(),
+            other_translations: vec![],
+            overview: String::new(),
+            declension: None,
+            verb: None,
+            sentences: vec![],
+            audio_url: None,
+            audio_file: None,
+            source: None,
+        }
+    }
 }
 
 impl TranslationInfo {
@@ -52,18 +170,17 @@ impl TranslationInfo {
         }
     }
 
-    fn other_translations_joined(&self) -> String {
+    pub fn other_translations_joined(&self) -> String {
         self.other_translations.join(", ")
     }
 
-    fn overview_in_one_line(&self) -> String {
+    pub fn overview_in_one_line(&self) -> String {
         self.overview.replace('\n', "; ")
     }
 
     fn overview_centered_with_walls(&self) -> String {
         let width = self.max_field_len() + 2;
-        return self
-            .overview
+        self.overview
             .split('\n')
             .map(|x| {
                 format!(
@@ -73,53 +190,87 @@ impl TranslationInfo {
                 )
             })
             .collect::<Vec<String>>()
-            .join("\n");
+            .join("\n")
     }
 
-    fn from_csv_string_record_slice(record: csv::StringRecord) -> Result<Self, Box<dyn Error>> {
-        let other_translations = record
-            .get(4)
-            .unwrap()
-            .to_string()
+    pub fn from_csv_string_record_slice(record: csv::StringRecord) -> Result<Self, Box<dyn Error>> {
+        let field = |i: usize| {
+            record.get(i).ok_or_else(|| {
+                let line = record.position().map_or(0, |p| p.line());
+                format!("Line {line} has {} columns, at least 8 are needed.", record.len())
+            })
+        };
+        let other_translations = field(4)?
             .split(", ")
             .map(|s| s.to_string())
             .collect();
-        let overview = record.get(5).unwrap().replace("; ", "\n");
-        let context_phrase = match record.get(6).unwrap() {
+        let overview = field(5)?.replace("; ", "\n");
+        let context_phrase = match field(6)? {
             "" => None,
             s => Some(s.to_string()),
         };
-        let context_phrase_translation = match record.get(7).unwrap() {
+        let context_phrase_translation = match field(7)? {
             "" => None,
             s => Some(s.to_string()),
         };
+        // older main.csv files don't have this column
+        let user_context_phrase = match record.get(8) {
+            None | Some("") => None,
+            Some(s) => Some(s.to_string()),
+        };
 
         Ok(Self {
-            search_query: record.get(0).unwrap().to_string(),
-            search_result: record.get(1).unwrap().to_string(),
-            title: record.get(2).unwrap().to_string(),
-            main_translation: record.get(3).unwrap().to_string(),
+            search_query: field(0)?.to_string(),
+            guid: note_guid(field(1)?),
+            search_result: field(1)?.to_string(),
+            title: field(2)?.to_string(),
+            main_translation: field(3)?.to_string(),
             other_translations,
             overview,
             context_phrase,
             context_phrase_translation,
+            user_context_phrase,
+            declension: None,
+            verb: None,
+            sentences: vec![],
+            audio_url: None,
+            audio_file: None,
+            source: None,
         })
     }
 
-    fn to_csv_string_record_slice(&self) -> String {
-        format!(
-            "{}{}{}{}{}{}{}{}",
-            self.search_query,
-            self.search_result,
-            self.title,
-            self.main_translation,
-            self.other_translations_joined(),
-            self.overview_in_one_line(),
-            self.context_phrase.as_ref().unwrap_or(&String::from("")),
-            self.context_phrase_translation
-                .as_ref()
-                .unwrap_or(&String::from(""))
-        )
+    // phrases to be shown on the card, user supplied one first
+    pub fn context_phrases(&self) -> Vec<&str> {
+        [&self.user_context_phrase, &self.context_phrase]
+            .into_iter()
+            .flatten()
+            .map(|s| s.as_str())
+            .collect()
+    }
+
+    // as when fetched with the phrase, returns false if nothing changed
+    pub fn set_user_context_phrase(&mut self, phrase: &str, keep_both_phrases: bool) -> bool {
+        let unchanged = self.user_context_phrase.as_deref() == Some(phrase)
+            && (keep_both_phrases || self.context_phrase.is_none());
+        if unchanged {
+            return false;
+        }
+        self.user_context_phrase = Some(String::from(phrase));
+        if !keep_both_phrases {
+            self.context_phrase = None;
+            self.context_phrase_translation = None;
+        }
+        true
+    }
+
+    // up to `n` example sentences in total, counting the scraped context phrase
+    pub fn more_sentences(&self, n: usize) -> Vec<&Sentence> {
+        let n_shown = self.context_phrase.is_some() as usize;
+        self.sentences
+            .iter()
+            .filter(|s| Some(&s.ru) != self.context_phrase.as_ref())
+            .take(n.saturating_sub(n_shown))
+            .collect()
     }
 
     fn other_translations_concatenated(&self) -> String {
@@ -146,97 +297,249 @@ impl fmt::Display for TranslationInfo {
             width=width,
             title_width=title_width,
         )?;
-        if let Some(c) = &self.context_phrase {
+        let context_phrases = self.context_phrases();
+        if !context_phrases.is_empty() {
             writeln!(f)?;
+        }
+        for c in context_phrases {
             write!(f, "\n{}", c)?;
         }
         if let Some(ct) = &self.context_phrase_translation {
             write!(f, "\n{}", ct,)?;
         }
+        if let Some(declension) = &self.declension {
+            write!(f, "\n\n{}", declension.to_box_table())?;
+        }
+        if let Some(verb) = &self.verb {
+            write!(f, "\n\n{}", verb.to_box_tables())?;
+        }
         Ok(())
     }
 }
 
+// a word from the suggestions that matches the search query
+#[derive(Debug, Serialize)]
+pub struct Candidate {
+    // stress is marked with an apostrophe after the vowel
+    pub ru: String,
+    pub translations: Vec<String>,
+    pub part_of_speech: Option<String>,
+}
+
+impl Candidate {
+    fn search_result(&self) -> String {
+        self.ru.replace('\'', "")
+    }
+}
+
+impl fmt::Display for Candidate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.ru.replace('\'', "\u{301}"))?;
+        if let Some(part_of_speech) = &self.part_of_speech {
+            write!(f, " ({part_of_speech})")?;
+        }
+        write!(f, ": {}", self.translations.join(", "))
+    }
+}
+
+// which suggestions are saved when a search query matches more than one word
+pub enum Pick {
+    First,
+    // 1-based, as shown to the user
+    Nth(usize),
+    All,
+    Interactive,
+}
+
+impl Pick {
+    // may be empty, e.g. when N is past the last suggestion
+    fn choose<'a>(&self, candidates: &'a [Candidate]) -> Vec<&'a Candidate> {
+        match self {
+            Pick::First => candidates.iter().take(1).collect(),
+            Pick::Nth(n) => candidates.iter().skip(n.saturating_sub(1)).take(1).collect(),
+            Pick::All => candidates.iter().collect(),
+            Pick::Interactive => choose_interactively(candidates),
+        }
+    }
+}
+
+// empty answer picks the first one, anything unreadable picks nothing
+fn choose_interactively(candidates: &[Candidate]) -> Vec<&Candidate> {
+    if candidates.len() == 1 {
+        return candidates.iter().collect();
+    }
+    for (i, candidate) in candidates.iter().enumerate() {
+        println!("{:>3}. {candidate}", i + 1);
+    }
+    print!("Pick one or more (e.g. `1 3`, `a` for all) [1]: ");
+    let _ = io::stdout().flush();
+    let mut answer = String::new();
+    if io::stdin().read_line(&mut answer).is_err() {
+        return vec![];
+    }
+    match answer.trim() {
+        "" => candidates.iter().take(1).collect(),
+        "a" => candidates.iter().collect(),
+        answer => answer
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter_map(|n| n.parse::<usize>().ok())
+            .filter_map(|n| candidates.get(n.checked_sub(1)?))
+            .collect(),
+    }
+}
+
+// shared by every lookup, so that the connection pool and the rate limit are shared too
+pub struct Fetcher {
+    client: reqwest::Client,
+    interval: Option<Mutex<Interval>>,
+}
+
+impl Fetcher {
+    // `max_requests_per_second` of None means no limit
+    pub fn new(max_requests_per_second: Option<f64>) -> Self {
+        let interval = max_requests_per_second
+            .filter(|rps| *rps > 0.0)
+            .map(|rps| {
+                let mut interval = time::interval(Duration::from_secs_f64(1.0 / rps));
+                interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+                Mutex::new(interval)
+            });
+        Self {
+            client: reqwest::Client::new(),
+            interval,
+        }
+    }
+
+    async fn get(&self, url: &str, query: &[(&str, &str)]) -> Result<Response, OrError> {
+        let mut attempt = 0;
+        loop {
+            match self.get_once(url, query).await {
+                Err(error) if error.is_transient() && attempt < MAX_RETRIES => {
+                    time::sleep(RETRY_BASE_DELAY * 2u32.pow(attempt)).await;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    async fn get_once(&self, url: &str, query: &[(&str, &str)]) -> Result<Response, OrError> {
+        if let Some(interval) = &self.interval {
+            interval.lock().await.tick().await;
+        }
+        Ok(self
+            .client
+            .get(url)
+            .query(query)
+            .header(USER_AGENT, &config::get().user_agent)
+            .send()
+            .await?
+            .error_for_status()?)
+    }
+
+    async fn get_text(&self, url: &str, query: &[(&str, &str)]) -> Result<String, OrError> {
+        Ok(self.get(url, query).await?.text().await?)
+    }
+
+    async fn get_bytes(&self, url: &str) -> Result<Vec<u8>, OrError> {
+        Ok(self.get(url, &[]).await?.bytes().await?.to_vec())
+    }
+}
+
 // first request, get some word to match search term
-async fn get_search_query_response_json(input_term: &str) -> Result<Value, Box<dyn Error>> {
-    let client = reqwest::Client::new();
-    let response = client
-        .get(format!(
-            "https://api.openrussian.org/suggestions?q={}&dummy=1654996242200&lang=en",
-            input_term
-        ))
-        .header(USER_AGENT, HEADER_USER_AGENT)
-        .send()
-        .await?
-        .text()
+async fn get_search_query_response_json(
+    fetcher: &Fetcher,
+    input_term: &str,
+) -> Result<Value, OrError> {
+    let response = fetcher
+        .get_text(
+            "https://api.openrussian.org/suggestions",
+            &[("q", input_term), ("dummy", "1654996242200"), ("lang", &config::get().language)],
+        )
         .await?;
-    let ret: Value = serde_json::from_str(response.as_str())?;
-    Ok(ret)
+    serde_json::from_str(response.as_str())
+        .map_err(|_| OrError::LayoutChanged(String::from("suggestions are not valid json")))
 }
 
-fn get_response_json_first_term(response_json: &Value) -> Option<String> {
-    let result_words = response_json["result"]["words"].as_array().unwrap();
+// translations come as plain strings or nested in lists and objects
+fn json_strings(value: &Value) -> Vec<String> {
+    match value {
+        Value::String(s) => vec![s.clone()],
+        Value::Array(values) => values.iter().flat_map(json_strings).collect(),
+        Value::Object(map) => map.get("tl").map(json_strings).unwrap_or_default(),
+        _ => vec![],
+    }
+}
+
+// words without translations are left out
+fn get_response_json_candidates(response_json: &Value) -> Result<Vec<Candidate>, OrError> {
+    let layout_changed = || OrError::LayoutChanged(String::from("unexpected suggestions json"));
+    let result_words = response_json["result"]["words"]
+        .as_array()
+        .ok_or_else(layout_changed)?;
+    let mut candidates = vec![];
     for word in result_words {
-        let tls = word["word"]["tls"].as_array().unwrap();
+        let tls = word["word"]["tls"].as_array().ok_or_else(layout_changed)?;
         if tls.is_empty() {
             continue
         }
         if let Some(result) = word["word"]["ru"].as_str() {
-            return Some(String::from(result));
+            candidates.push(Candidate {
+                ru: String::from(result),
+                translations: tls.iter().flat_map(json_strings).collect(),
+                part_of_speech: word["word"]["type"].as_str().map(String::from),
+            });
         }
     }
-    None
+    Ok(candidates)
 }
 
-async fn get_search_result(search_query: &str) -> Result<Option<String>, Box<dyn Error>> {
-    let full_res_json = match get_search_query_response_json(search_query).await {
-        Ok(res) => res,
-        Err(error) => return Err(error),
-    };
-
-    if let Some(first_term) = get_response_json_first_term(&full_res_json) {
-        Ok(Some(first_term.replace('\'', "")))
-    } else {
-        Ok(None)
+pub async fn get_candidates(
+    fetcher: &Fetcher,
+    search_query: &str,
+) -> Result<Vec<Candidate>, OrError> {
+    let full_res_json = get_search_query_response_json(fetcher, search_query).await?;
+    let candidates = get_response_json_candidates(&full_res_json)?;
+    if candidates.is_empty() {
+        return Err(OrError::NoSuggestion(String::from(search_query)));
     }
+    Ok(candidates)
 }
 
 // second request, get detailes of matched word
-async fn get_search_result_response_text(search_result: &str) -> Result<String, reqwest::Error> {
-    let client = reqwest::Client::new();
-    client
-        .get(format!("https://en.openrussian.org/ru/{}", search_result))
-        .header(USER_AGENT, HEADER_USER_AGENT)
-        .send()
-        .await?
-        .text()
+fn get_search_result_url(search_result: &str) -> String {
+    format!("https://{}.openrussian.org/ru/{}", config::get().language, search_result)
+}
+
+async fn get_search_result_response_text(
+    fetcher: &Fetcher,
+    search_result: &str,
+) -> Result<String, OrError> {
+    fetcher
+        .get_text(&get_search_result_url(search_result), &[])
         .await
 }
 
-fn _get_class_content_from_html(html: Html, selector_str: &str) -> Result<String, Box<dyn Error>> {
+fn _get_class_content_from_html(html: Html, selector_str: &str) -> Option<String> {
     let selector = scraper::Selector::parse(selector_str).unwrap();
-    let first_text = html
-        .select(&selector)
+    html.select(&selector)
         .map(|x| x.inner_html()) // here it maybe text
-        .next();
-    match first_text {
-        Some(text) => Ok(text),
-        None => Err(format!("No element for \"{selector_str}\" found.").into()),
-    }
+        .next()
 }
 
-fn get_selector_text_from_bigger_text(
-    selector_str: &str,
-    bigger_text: &str,
-) -> Result<String, Box<dyn Error>> {
+fn get_selector_text_from_bigger_text(selector_str: &str, bigger_text: &str) -> Option<String> {
     let document = Html::parse_document(bigger_text);
     _get_class_content_from_html(document, selector_str)
 }
-fn get_first_sentence_and_translation_from_response_text(
-    response_text: &str,
-) -> Result<(String, String), Box<dyn Error>> {
-    let sentences_text = get_selector_text_from_bigger_text("ul.sentences > li", response_text)?;
-    let ru_html_text = get_selector_text_from_bigger_text(".ru", &sentences_text)?;
+
+// the parts every word page has, without them the page is not what we expect
+fn get_section_text(selector_str: &str, bigger_text: &str) -> Result<String, OrError> {
+    get_selector_text_from_bigger_text(selector_str, bigger_text)
+        .ok_or_else(|| OrError::LayoutChanged(format!("no `{selector_str}` element")))
+}
+
+fn get_sentence_from_sentence_text(sentence_text: &str) -> Option<Sentence> {
+    let ru_html_text = get_selector_text_from_bigger_text(".ru", sentence_text)?;
     let ru_html = Html::parse_fragment(ru_html_text.as_str());
     let span_a_selector = scraper::Selector::parse("a,span").unwrap();
     let ru_sentence = ru_html
@@ -245,12 +548,53 @@ fn get_first_sentence_and_translation_from_response_text(
         .collect::<Vec<&str>>()
         .join("");
 
-    let en_sentence = get_selector_text_from_bigger_text(".tl span", &sentences_text)?;
+    let en_sentence = get_selector_text_from_bigger_text(".tl span", sentence_text)?;
+
+    Some(Sentence {
+        ru: ru_sentence,
+        en: en_sentence,
+    })
+}
+
+fn get_sentences_from_response_text(response_text: &str) -> Vec<Sentence> {
+    let document = Html::parse_document(response_text);
+    let li_selector = scraper::Selector::parse("ul.sentences > li").unwrap();
+    document
+        .select(&li_selector)
+        .filter_map(|li| get_sentence_from_sentence_text(&li.inner_html()))
+        .collect()
+}
+
+// relative links are resolved against the word page
+fn get_audio_url_from_response_text(response_text: &str, page_url: &str) -> Option<String> {
+    let document = Html::parse_document(response_text);
+    let audio_selector =
+        scraper::Selector::parse("audio[src], audio source[src], a[href$='.mp3']").unwrap();
+    let element = document.select(&audio_selector).next()?;
+    let src = element
+        .value()
+        .attr("src")
+        .or_else(|| element.value().attr("href"))?;
+    Url::parse(page_url).ok()?.join(src).ok().map(String::from)
+}
 
-    Ok((ru_sentence, en_sentence))
+// the file name is what the anki card refers to, None if the download failed
+async fn download_audio(fetcher: &Fetcher, audio_url: &str, search_result: &str) -> Option<String> {
+    let extension = Path::new(Url::parse(audio_url).ok()?.path())
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or("mp3")
+        .to_string();
+    let file_name = format!("oraki-{search_result}.{extension}");
+    let file_path = get_media_dir().ok()?.join(&file_name);
+    if !file_path.is_file() {
+        let bytes = fetcher.get_bytes(audio_url).await.ok()?;
+        std::fs::write(&file_path, bytes).ok()?;
+    }
+    Some(file_name)
 }
 
-fn get_overview_from_basics_text(basics_text: &str) -> Result<String, Box<dyn Error>> {
+fn get_overview_from_basics_text(basics_text: &str) -> Option<String> {
     let overview_html_text = get_selector_text_from_bigger_text(".overview", basics_text)?;
 
     let overview_html = Html::parse_fragment(overview_html_text.as_str());
@@ -262,65 +606,214 @@ fn get_overview_from_basics_text(basics_text: &str) -> Result<String, Box<dyn Er
         .collect::<Vec<&str>>()
         .join("\n")
         .replace("\n \n", " ");
-    Ok(text)
+    Some(text)
 }
 
-fn get_other_translations_from_translations_text(
-    basics_text: &str,
-) -> Result<Vec<String>, Box<dyn Error>> {
+fn get_other_translations_from_translations_text(basics_text: &str) -> Vec<String> {
     let document = Html::parse_fragment(basics_text);
     let other_translations_text = _get_class_content_from_html(document, ".tl-also")
         .unwrap_or(String::from(DEFAULT_EMPTY_VALUE));
 
     let re = Regex::new("Also<.*>").unwrap();
-    Ok(re
-        .replace(other_translations_text.as_str(), "")
+    re.replace(other_translations_text.as_str(), "")
         .split(", ")
         .map(String::from)
-        .collect::<Vec<String>>())
+        .collect::<Vec<String>>()
+}
+
+// where words are looked up: OpenRussian, or the offline index when it is used
+enum Dictionary {
+    Online,
+    Offline(OfflineIndex),
+}
+
+impl Dictionary {
+    fn open() -> Result<Self, OrError> {
+        match config::get().offline {
+            true => Ok(Dictionary::Offline(OfflineIndex::open()?)),
+            false => Ok(Dictionary::Online),
+        }
+    }
+
+    async fn get_candidates(
+        &self,
+        fetcher: &Fetcher,
+        search_query: &str,
+    ) -> Result<Vec<Candidate>, OrError> {
+        match self {
+            Dictionary::Offline(index) => index.get_candidates(search_query),
+            Dictionary::Online => get_candidates(fetcher, search_query).await,
+        }
+    }
+
+    // the offline index looks the word up by its accented form, so it finds the candidate again
+    async fn get_translation_info(
+        &self,
+        fetcher: &Fetcher,
+        search_query: &str,
+        search_result: String,
+        user_context_phrase: Option<&str>,
+        keep_both_phrases: bool,
+    ) -> Result<TranslationInfo, OrError> {
+        match self {
+            Dictionary::Offline(index) => {
+                let candidates = index.get_candidates(search_query)?;
+                let candidate = candidates
+                    .iter()
+                    .find(|c| c.search_result() == search_result)
+                    .ok_or_else(|| OrError::NoSuggestion(String::from(search_query)))?;
+                index.get_translation_info(
+                    search_query,
+                    candidate,
+                    user_context_phrase,
+                    keep_both_phrases,
+                )
+            }
+            Dictionary::Online => {
+                get_translation_info_for_search_result(
+                    fetcher,
+                    search_query,
+                    search_result,
+                    user_context_phrase,
+                    keep_both_phrases,
+                )
+                .await
+            }
+        }
+    }
+}
+
+pub struct SearchResults {
+    // empty when the first pick was answered from the saved searches, without looking it up,
+    // see `find_candidates`
+    pub candidates: Vec<Candidate>,
+    // every chosen word, and whether it was already saved
+    pub translation_infos: Vec<(TranslationInfo, bool)>,
+}
+
+// the words matching `search_query`, in the offline index when it is used
+pub async fn find_candidates(
+    fetcher: &Fetcher,
+    search_query: &str,
+) -> Result<Vec<Candidate>, OrError> {
+    Dictionary::open()?.get_candidates(fetcher, search_query).await
 }
 
 // second argument on return refers if result already existed
-pub async fn get_translation_info(search_query: &str) -> Result<(TranslationInfo, bool), Box<dyn Error>> {
-    if let Ok(Some(translation_info)) = get_cached_translation_info_for_query(search_query) {
-        return Ok((translation_info, true));
-    }
-    let search_result = match get_search_result(search_query).await {
-        Ok(result) => match result {
-            Some(result) => result,
-            None => return Err(format!("No results found for {search_query}.").into()),
-        },
-        Err(error) => {
-            return Err(format!(
-                "Couldn't find search result for term `{search_query}` with error:\n{error}"
-            )
-            .into())
+// if `user_context_phrase` is given, the scraped phrase is only kept with `keep_both_phrases`
+pub async fn get_translation_info(
+    fetcher: &Fetcher,
+    search_query: &str,
+    user_context_phrase: Option<&str>,
+    keep_both_phrases: bool,
+    pick: &Pick,
+) -> Result<SearchResults, OrError> {
+    if let Pick::First = pick {
+        if let Ok(Some(translation_info)) = get_cached_translation_info_for_query(search_query) {
+            return Ok(SearchResults {
+                candidates: vec![],
+                translation_infos: vec![(translation_info, true)],
+            });
         }
-    };
-    let response_text = get_search_result_response_text(&search_result).await?;
-    let basics_text = get_selector_text_from_bigger_text(".basics", response_text.as_str())?;
+    }
+    let dictionary = Dictionary::open()?;
+    let candidates = dictionary.get_candidates(fetcher, search_query).await?;
+    let chosen = pick.choose(&candidates);
+    if chosen.is_empty() {
+        return Err(OrError::NoSuggestion(String::from(search_query)));
+    }
+    let mut translation_infos = vec![];
+    for candidate in chosen {
+        let search_result = candidate.search_result();
+        let cached = Storage::open()
+            .and_then(|s| s.get_by_query_and_result(search_query, &search_result));
+        if let Ok(Some(translation_info)) = cached {
+            translation_infos.push((translation_info, true));
+            continue;
+        }
+        let translation_info = dictionary
+            .get_translation_info(
+                fetcher,
+                search_query,
+                search_result,
+                user_context_phrase,
+                keep_both_phrases,
+            )
+            .await?;
+        translation_infos.push((translation_info, false));
+    }
+    Ok(SearchResults {
+        candidates,
+        translation_infos,
+    })
+}
+
+// looks a saved entry up again, keeping the phrase the user gave and its source
+pub async fn refetch_translation_info(
+    fetcher: &Fetcher,
+    translation_info: &TranslationInfo,
+) -> Result<TranslationInfo, OrError> {
+    let search_query = translation_info.search_query.as_str();
+    let user_context_phrase = translation_info.user_context_phrase.as_deref();
+    let keep_both_phrases = user_context_phrase.is_some() && translation_info.context_phrase.is_some();
+    let mut refetched = Dictionary::open()?
+        .get_translation_info(
+            fetcher,
+            search_query,
+            translation_info.search_result.clone(),
+            user_context_phrase,
+            keep_both_phrases,
+        )
+        .await?;
+    refetched.source = translation_info.source.clone();
+    Ok(refetched)
+}
+
+async fn get_translation_info_for_search_result(
+    fetcher: &Fetcher,
+    search_query: &str,
+    search_result: String,
+    user_context_phrase: Option<&str>,
+    keep_both_phrases: bool,
+) -> Result<TranslationInfo, OrError> {
+    let response_text = get_search_result_response_text(fetcher, &search_result).await?;
+    let basics_text = get_section_text(".basics", response_text.as_str())?;
 
     // get context phrase
     let mut context_phrase_translation = None;
     let mut context_phrase = None;
-    let first_sentence_result =
-        get_first_sentence_and_translation_from_response_text(response_text.as_str());
-    if let Ok(first_sentence) = first_sentence_result {
-        context_phrase = Some(first_sentence.0);
-        context_phrase_translation = Some(first_sentence.1);
+    let sentences = get_sentences_from_response_text(response_text.as_str());
+    if let Some(first_sentence) = sentences.first() {
+        if user_context_phrase.is_none() || keep_both_phrases {
+            context_phrase = Some(first_sentence.ru.clone());
+            context_phrase_translation = Some(first_sentence.en.clone());
+        }
     }
 
-    let title = get_selector_text_from_bigger_text(".bare span", basics_text.as_str())?;
-    let overview = get_overview_from_basics_text(basics_text.as_str())?;
+    let title = get_selector_text_from_bigger_text(".bare span", basics_text.as_str())
+        .ok_or(OrError::MissingField("title"))?;
+    let overview = get_overview_from_basics_text(basics_text.as_str())
+        .ok_or(OrError::MissingField("overview"))?;
 
-    let translations_text =
-        get_selector_text_from_bigger_text(".translations", response_text.as_str())?;
-    let main_translation = get_selector_text_from_bigger_text(".tl", translations_text.as_str())?;
+    let translations_text = get_section_text(".translations", response_text.as_str())?;
+    let main_translation = get_selector_text_from_bigger_text(".tl", translations_text.as_str())
+        .ok_or(OrError::MissingField("main_translation"))?;
     let other_translations =
-        get_other_translations_from_translations_text(translations_text.as_str())?;
+        get_other_translations_from_translations_text(translations_text.as_str());
+    let declension = Declension::from_response_text(response_text.as_str());
+    let verb = Verb::from_response_text(response_text.as_str());
+    let audio_url = get_audio_url_from_response_text(
+        response_text.as_str(),
+        &get_search_result_url(&search_result),
+    );
+    let audio_file = match &audio_url {
+        Some(url) => download_audio(fetcher, url, &search_result).await,
+        None => None,
+    };
 
-    Ok((TranslationInfo {
+    Ok(TranslationInfo {
         search_query: String::from(search_query),
+        guid: note_guid(&search_result),
         search_result,
         title,
         main_translation,
@@ -328,112 +821,160 @@ pub async fn get_translation_info(search_query: &str) -> Result<(TranslationInfo
         overview,
         context_phrase,
         context_phrase_translation,
-    }, false))
+        user_context_phrase: user_context_phrase.map(String::from),
+        declension,
+        verb,
+        sentences,
+        audio_url,
+        audio_file,
+        source: None,
+    })
 }
 
 #[derive(Debug)]
 pub struct FileResult {
-    pub failed_results: Vec<String>,
+    // search query and the reason it failed
+    pub failed_results: Vec<(String, String)>,
     pub fetched_results: Vec<String>,
     pub existent_results: Vec<String>,
     pub n_total: u64,
 }
 
+// a line is either `query phrase...` or, for expressions, `query words\tphrase...`
+fn split_list_file_line(line: &str) -> Option<(&str, Option<&str>)> {
+    let (search_query, user_context_phrase) = match line.split_once('\t') {
+        Some((search_query, rest)) => (search_query.trim(), rest.trim()),
+        None => {
+            let line = line.trim();
+            line.split_once(char::is_whitespace)
+                .map(|(q, rest)| (q, rest.trim()))
+                .unwrap_or((line, ""))
+        }
+    };
+    if search_query.is_empty() || search_query.starts_with('#') {
+        return None;
+    }
+    let user_context_phrase = match user_context_phrase {
+        "" => None,
+        s => Some(s),
+    };
+    Some((search_query, user_context_phrase))
+}
+
+// a line of failed.out is `query\treason`
+fn split_failed_file_line(line: &str) -> Option<(&str, Option<&str>)> {
+    let search_query = line.split('\t').next()?.trim();
+    if search_query.is_empty() {
+        return None;
+    }
+    Some((search_query, None))
+}
+
+// with `retry_failed`, `file_name` is a failed.out from a previous run
+// with `resume`, lines already in the checkpoint of an interrupted run are skipped
 pub async fn append_translation_infos_from_file_name(
-    file_name: &str,
+    fetcher: &Fetcher,
+    file_name: &Path,
+    keep_both_phrases: bool,
+    n_jobs: usize,
+    retry_failed: bool,
+    resume: bool,
+    pick: &Pick,
 ) -> Result<FileResult, Box<dyn Error>> {
     let file = std::fs::File::open(file_name)?;
     let file = BufReader::new(file);
-    let file_lines = file.lines().collect::<Vec<Result<String, std::io::Error>>>();
+    let file_lines = file.lines().collect::<Result<Vec<String>, std::io::Error>>()?;
+    let split_line = match retry_failed {
+        true => split_failed_file_line,
+        false => split_list_file_line,
+    };
+    let queries = file_lines
+        .iter()
+        .enumerate()
+        .filter_map(|(line_number, line)| {
+            split_line(line).map(|(q, phrase)| (line_number, q, phrase))
+        })
+        .collect::<Vec<(usize, &str, Option<&str>)>>();
 
-    let n_lines = file_lines.len() as u64;
+    let (mut checkpoint, done_entries) = match resume {
+        true => Checkpoint::resume(file_name)?,
+        false => (Checkpoint::create(file_name)?, vec![]),
+    };
+
+    let n_total = queries.len() as u64;
     // progress bar
-    let pb = ProgressBar::new(n_lines);
+    let pb = ProgressBar::new(n_total);
     pb.set_style(
         ProgressStyle::default_bar()
         .template("[{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len} ({eta})")?
         .progress_chars("#>-"),
     );
 
-    let mut failed_results: Vec<String> = vec![];
+    let mut failed_results: Vec<(String, String)> = vec![];
     let mut existent_results: Vec<String> = vec![];
     let mut fetched_results: Vec<String> = vec![];
-    for result in file_lines.into_iter() {
-        pb.inc(1);
-        let line = result?;
-        let mut line_words = line.split_whitespace();
-        let Some(search_query) = line_words.next() else {
-            continue
-        };
-        if search_query.starts_with('#') {
-            continue;
+    let mut done_line_numbers = HashSet::new();
+    for entry in done_entries {
+        done_line_numbers.insert(entry.line_number);
+        match entry.outcome {
+            Outcome::Existent => existent_results.push(entry.search_query),
+            Outcome::Fetched => fetched_results.push(entry.search_query),
+            Outcome::Failed(reason) => failed_results.push((entry.search_query, reason)),
         }
-
-        if let Ok(already_existed) = super::run(search_query, false).await {
-            if already_existed {
+    }
+    pb.set_position(done_line_numbers.len() as u64);
+
+    let mut lookups = stream::iter(queries)
+        .filter(|(line_number, _, _)| future::ready(!done_line_numbers.contains(line_number)))
+        .map(|(line_number, search_query, user_context_phrase)| async move {
+            let result =
+                super::run(fetcher, search_query, user_context_phrase, keep_both_phrases, pick, None, 0)
+                    .await;
+            (line_number, search_query, result)
+        })
+        .buffer_unordered(n_jobs.max(1));
+    while let Some((line_number, search_query, result)) = lookups.next().await {
+        pb.inc(1);
+        let outcome = match result {
+            Ok(true) => {
                 pb.println(format!("Got existent info for {search_query}..."));
                 existent_results.push(search_query.to_string());
-            } else {
+                Outcome::Existent
+            }
+            Ok(false) => {
                 pb.println(format!("Got new info for {search_query}..."));
                 fetched_results.push(search_query.to_string());
+                Outcome::Fetched
+            }
+            Err(error) => {
+                pb.println(format!("Failed getting info for {search_query}: {error}."));
+                failed_results.push((search_query.to_string(), error.to_string()));
+                Outcome::Failed(error.to_string())
             }
-        }
-        else {
-            pb.println(format!("Failed getting info for {search_query}."));
-            failed_results.push(search_query.to_string());
-            continue
         };
+        checkpoint.record(&CheckpointEntry {
+            line_number,
+            search_query: search_query.to_string(),
+            outcome,
+        })?;
     }
+    pb.finish();
+    checkpoint.finish()?;
     Ok(FileResult {
         failed_results,
         fetched_results,
         existent_results,
-        n_total: n_lines 
+        n_total,
     })
 }
 
 pub fn get_cached_translation_info_for_query(
     search_query: &str,
 ) -> Result<Option<TranslationInfo>, Box<dyn Error>> {
-    let mut reader = ReaderBuilder::new()
-        .delimiter(b'|')
-        .from_path(get_main_csv_path()?)?;
-    for record in reader.records() {
-        let result = record?;
-        if result.get(0) == Some(search_query) {
-            return Ok(Some(TranslationInfo::from_csv_string_record_slice(result)?));
-        }
-    }
-    Ok(None)
+    Storage::open()?.get_by_query(search_query)
 }
 
 pub fn append_translation_info(translation_info: &TranslationInfo) -> Result<(), Box<dyn Error>> {
-    let write_file = OpenOptions::new()
-        .write(true)
-        .append(true)
-        .open(get_main_csv_path()?)
-        .unwrap();
-    let mut writer = WriterBuilder::new().delimiter(b'|').from_writer(write_file);
-    if get_cached_translation_info_for_query(&translation_info.search_query)?.is_some() {
-        return Ok(());
-    }
-    writer.write_record([
-        translation_info.search_query.as_str(),
-        translation_info.search_result.as_str(),
-        translation_info.title.as_str(),
-        translation_info.main_translation.as_str(),
-        translation_info.other_translations_joined().as_str(),
-        translation_info.overview_in_one_line().as_str(),
-        translation_info
-            .context_phrase
-            .as_ref()
-            .unwrap_or(&String::from(""))
-            .as_str(),
-        translation_info
-            .context_phrase_translation
-            .as_ref()
-            .unwrap_or(&String::from(""))
-            .as_str(),
-    ])?;
+    Storage::open()?.insert(translation_info)?;
     Ok(())
 }
@@ -0,0 +1,91 @@
+use crate::or::{Candidate, SearchResults, TranslationInfo};
+use crate::tags::tags;
+use clap::ValueEnum;
+use serde::Serialize;
+use std::error::Error;
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+pub enum SearchOutput {
+    /// The card, as a table.
+    Pretty,
+    /// One object with the candidates and every saved result.
+    Json,
+    /// One tab-separated field per line, results separated by an empty line.
+    Plain,
+}
+
+#[derive(Serialize)]
+struct JsonResult<'a> {
+    // already saved before this search
+    cached: bool,
+    #[serde(flatten)]
+    translation_info: &'a TranslationInfo,
+}
+
+#[derive(Serialize)]
+struct JsonOutput<'a> {
+    search_query: &'a str,
+    candidates: &'a [Candidate],
+    results: Vec<JsonResult<'a>>,
+}
+
+pub fn print_json(search_query: &str, search_results: &SearchResults) -> Result<(), Box<dyn Error>> {
+    let output = JsonOutput {
+        search_query,
+        candidates: &search_results.candidates,
+        results: search_results
+            .translation_infos
+            .iter()
+            .map(|(translation_info, cached)| JsonResult {
+                cached: *cached,
+                translation_info,
+            })
+            .collect(),
+    };
+    println!("{}", serde_json::to_string_pretty(&output)?);
+    Ok(())
+}
+
+// tabs and newlines inside a value would split the line
+fn plain_value(value: &str) -> String {
+    value.replace(['\t', '\n'], " ")
+}
+
+fn print_plain_field(name: &str, value: Option<&str>) {
+    if let Some(value) = value {
+        println!("{name}\t{}", plain_value(value));
+    }
+}
+
+pub fn print_plain(search_results: &SearchResults) {
+    for candidate in &search_results.candidates {
+        println!(
+            "candidate\t{}\t{}\t{}",
+            plain_value(&candidate.ru),
+            candidate.part_of_speech.as_deref().unwrap_or(""),
+            plain_value(&candidate.translations.join(", "))
+        );
+    }
+    for (translation_info, cached) in &search_results.translation_infos {
+        println!();
+        println!("cached\t{cached}");
+        print_plain_field("search_query", Some(&translation_info.search_query));
+        print_plain_field("search_result", Some(&translation_info.search_result));
+        print_plain_field("title", Some(&translation_info.title));
+        print_plain_field("main_translation", Some(&translation_info.main_translation));
+        print_plain_field("other_translations", Some(&translation_info.other_translations_joined()));
+        print_plain_field("overview", Some(&translation_info.overview_in_one_line()));
+        print_plain_field("context_phrase", translation_info.context_phrase.as_deref());
+        print_plain_field(
+            "context_phrase_translation",
+            translation_info.context_phrase_translation.as_deref(),
+        );
+        print_plain_field("user_context_phrase", translation_info.user_context_phrase.as_deref());
+        print_plain_field("tags", Some(&tags(translation_info).join(" ")));
+        print_plain_field("audio_file", translation_info.audio_file.as_deref());
+        print_plain_field("guid", Some(&translation_info.guid));
+        for sentence in &translation_info.sentences {
+            println!("sentence\t{}\t{}", plain_value(&sentence.ru), plain_value(&sentence.en));
+        }
+    }
+}
@@ -0,0 +1,199 @@
+use crate::anki::{create_deck_from_storage, SentenceChoice};
+use crate::config;
+use crate::or::{self, Fetcher, Pick, TranslationInfo};
+use crate::storage::Storage;
+use crate::templates::TemplateSet;
+use crate::utils::get_repl_history_path;
+use rustyline::error::ReadlineError;
+use rustyline::DefaultEditor;
+use std::collections::HashMap;
+use std::error::Error;
+
+const PROMPT: &str = "oraki> ";
+
+const HELP: &str = "\
+Type a word or expression to search it. Commands:
+  :sentences [N]  show the example sentences of the last word, or only N of them
+  :undo           remove the last entry saved in this session
+  :history        list the searches of this session
+  :compile        create the Anki deck from the saved searches
+  :help           show this message
+  :quit           leave, as does Ctrl-D";
+
+// one lookup of the session, for `:history`
+struct Lookup {
+    search_query: String,
+    title: String,
+    main_translation: String,
+    cached: bool,
+}
+
+struct Repl {
+    fetcher: Fetcher,
+    storage: Storage,
+    // saved entries by search query, so that repeated searches don't touch the database
+    index: HashMap<String, TranslationInfo>,
+    // entries saved in this session, the last one is removed by `:undo`
+    saved: Vec<(String, String)>,
+    lookups: Vec<Lookup>,
+    // search query of the word shown last
+    last: Option<String>,
+}
+
+impl Repl {
+    fn new() -> Result<Self, Box<dyn Error>> {
+        let storage = Storage::open()?;
+        let mut index = HashMap::new();
+        for translation_info in storage.all()? {
+            index
+                .entry(translation_info.search_query.clone())
+                .or_insert(translation_info);
+        }
+        Ok(Self {
+            fetcher: Fetcher::new(None),
+            storage,
+            index,
+            saved: vec![],
+            lookups: vec![],
+            last: None,
+        })
+    }
+
+    async fn search(&mut self, search_query: &str) -> Result<(), Box<dyn Error>> {
+        let cached = match self.index.contains_key(search_query) {
+            true => true,
+            false => {
+                let results =
+                    or::get_translation_info(&self.fetcher, search_query, None, false, &Pick::First)
+                        .await?;
+                // the first pick is a single entry
+                let (translation_info, already_existed) = results
+                    .translation_infos
+                    .into_iter()
+                    .next()
+                    .ok_or("No result.")?;
+                let inserted = self.storage.insert(&translation_info)?;
+                if inserted {
+                    self.saved.push((
+                        translation_info.search_query.clone(),
+                        translation_info.search_result.clone(),
+                    ));
+                }
+                self.index.insert(search_query.to_string(), translation_info);
+                already_existed || !inserted
+            }
+        };
+        let translation_info = &self.index[search_query];
+        println!("{translation_info}");
+        self.lookups.push(Lookup {
+            search_query: search_query.to_string(),
+            title: translation_info.title.clone(),
+            main_translation: translation_info.main_translation.clone(),
+            cached,
+        });
+        self.last = Some(search_query.to_string());
+        Ok(())
+    }
+
+    fn sentences(&self, n: Option<usize>) -> Result<(), Box<dyn Error>> {
+        let translation_info = self
+            .last
+            .as_ref()
+            .and_then(|q| self.index.get(q))
+            .ok_or("No word searched yet.")?;
+        let n = n.unwrap_or(translation_info.sentences.len());
+        if translation_info.sentences.is_empty() {
+            println!("No example sentences for {}.", translation_info.title);
+        }
+        for sentence in translation_info.sentences.iter().take(n) {
+            println!("\n{}\n{}", sentence.ru, sentence.en);
+        }
+        Ok(())
+    }
+
+    fn undo(&mut self) -> Result<(), Box<dyn Error>> {
+        let (search_query, search_result) = self
+            .saved
+            .pop()
+            .ok_or("Nothing was saved in this session.")?;
+        self.storage.remove_entry(&search_query, &search_result)?;
+        if let Some(translation_info) = self.index.remove(&search_query) {
+            println!("Removed {}.", translation_info.title);
+        }
+        if self.last.as_ref() == Some(&search_query) {
+            self.last = None;
+        }
+        Ok(())
+    }
+
+    fn history(&self) {
+        for lookup in &self.lookups {
+            let cached = match lookup.cached {
+                true => " (already saved)",
+                false => "",
+            };
+            println!(
+                "{} → {}: {}{cached}",
+                lookup.search_query, lookup.title, lookup.main_translation
+            );
+        }
+    }
+
+    fn compile(&self) -> Result<(), Box<dyn Error>> {
+        let templates = TemplateSet::load(None)?;
+        create_deck_from_storage(SentenceChoice::First, &config::get().cards, false, None, &templates)
+    }
+
+    // returns false when the session should end
+    async fn handle(&mut self, line: &str) -> Result<bool, Box<dyn Error>> {
+        let mut words = line.split_whitespace();
+        match words.next() {
+            Some(":q" | ":quit" | ":exit") => return Ok(false),
+            Some(":h" | ":help") => println!("{HELP}"),
+            Some(":sentences") => {
+                let n = words
+                    .next()
+                    .map(|n| n.parse::<usize>().map_err(|_| format!("`{n}` is not a number.")))
+                    .transpose()?;
+                self.sentences(n)?
+            }
+            Some(":undo") => self.undo()?,
+            Some(":history") => self.history(),
+            Some(":compile") => self.compile()?,
+            Some(command) if command.starts_with(':') => {
+                return Err(format!("Unknown command `{command}`, see `:help`.").into())
+            }
+            _ => self.search(line).await?,
+        }
+        Ok(true)
+    }
+}
+
+pub async fn repl() -> Result<(), Box<dyn Error>> {
+    let mut repl = Repl::new()?;
+    let mut editor = DefaultEditor::new()?;
+    let history_path = get_repl_history_path()?;
+    // there is no history before the first session
+    let _ = editor.load_history(&history_path);
+    println!("Type a word to search it, `:help` for commands.");
+    loop {
+        let line = match editor.readline(PROMPT) {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(error) => return Err(error.into()),
+        };
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        editor.add_history_entry(line)?;
+        match repl.handle(line).await {
+            Ok(true) => {}
+            Ok(false) => break,
+            Err(error) => eprintln!("error: {error}"),
+        }
+    }
+    editor.save_history(&history_path)?;
+    Ok(())
+}
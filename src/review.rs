@@ -0,0 +1,443 @@
+use crate::cloze::normalize;
+use crate::or::TranslationInfo;
+use crate::storage::Storage;
+use crate::tags::part_of_speech;
+use clap::ValueEnum;
+use std::collections::VecDeque;
+use std::error::Error;
+use std::io::{self, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+// lowest ease SM-2 allows, below it hard words would come back every day
+const MIN_EASE: f64 = 1.3;
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+pub enum Direction {
+    /// Show the russian word, answer with its translation.
+    Recognition,
+    /// Show the translation, answer with the russian word.
+    Production,
+    /// Both, each with its own schedule.
+    Both,
+}
+
+impl Direction {
+    // as kept in the review_state table
+    fn name(&self) -> &'static str {
+        match self {
+            Direction::Recognition => "recognition",
+            Direction::Production => "production",
+            Direction::Both => "both",
+        }
+    }
+}
+
+// SM-2 scheduling of one entry in one direction
+#[derive(Debug, Clone)]
+pub struct ReviewState {
+    pub ease: f64,
+    pub interval_days: i64,
+    // correct answers in a row
+    pub repetitions: i64,
+    // unix time of the next review
+    pub due: i64,
+}
+
+impl Default for ReviewState {
+    fn default() -> Self {
+        Self {
+            ease: 2.5,
+            interval_days: 0,
+            repetitions: 0,
+            due: 0,
+        }
+    }
+}
+
+impl ReviewState {
+    // `quality` goes from 0 (forgotten) to 5 (perfect), as in SM-2
+    fn schedule(&mut self, quality: u8, now: i64) {
+        if quality >= 3 {
+            self.interval_days = match self.repetitions {
+                0 => 1,
+                1 => 6,
+                _ => (self.interval_days as f64 * self.ease).round() as i64,
+            };
+            self.repetitions += 1;
+        } else {
+            self.interval_days = 1;
+            self.repetitions = 0;
+        }
+        let missing = f64::from(5 - quality);
+        self.ease = (self.ease + 0.1 - missing * (0.08 + missing * 0.02)).max(MIN_EASE);
+        self.due = now + self.interval_days * SECONDS_PER_DAY;
+    }
+}
+
+#[derive(PartialEq)]
+enum Grade {
+    Correct,
+    // one letter off
+    Close,
+    Wrong,
+}
+
+impl Grade {
+    fn quality(&self) -> u8 {
+        match self {
+            Grade::Correct => 4,
+            Grade::Close => 3,
+            Grade::Wrong => 1,
+        }
+    }
+}
+
+// stress, ё, case and punctuation don't count
+fn normalize_answer(answer: &str) -> String {
+    normalize(&answer.replace('\'', ""))
+        .chars()
+        .map(|c| if c.is_alphanumeric() { c } else { ' ' })
+        .collect::<String>()
+        .split_whitespace()
+        .collect::<Vec<&str>>()
+        .join(" ")
+}
+
+fn without_parentheses(text: &str) -> String {
+    let mut depth = 0;
+    text.chars()
+        .filter(|c| {
+            match c {
+                '(' => depth += 1,
+                ')' => depth -= 1,
+                _ => return depth == 0,
+            }
+            false
+        })
+        .collect()
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut previous = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let current = row[j + 1];
+            row[j + 1] = match ca == *cb {
+                true => previous,
+                false => 1 + previous.min(row[j]).min(row[j + 1]),
+            };
+            previous = current;
+        }
+    }
+    row[b.len()]
+}
+
+struct Card<'a> {
+    translation_info: &'a TranslationInfo,
+    direction: Direction,
+    state: ReviewState,
+    // answered wrong earlier in the session, asked again without being scheduled again
+    again: bool,
+}
+
+impl Card<'_> {
+    fn prompt(&self) -> String {
+        let translation_info = self.translation_info;
+        match self.direction {
+            Direction::Production => match part_of_speech(translation_info) {
+                Some(part_of_speech) => {
+                    format!("{} ({part_of_speech})", translation_info.main_translation)
+                }
+                None => translation_info.main_translation.clone(),
+            },
+            _ => translation_info.title.clone(),
+        }
+    }
+
+    // every answer taken as right, a translation may list a few meanings
+    fn expected(&self) -> Vec<String> {
+        let translation_info = self.translation_info;
+        let answers: Vec<String> = match self.direction {
+            Direction::Production => vec![
+                translation_info.title.clone(),
+                translation_info.search_result.clone(),
+            ],
+            _ => [translation_info.main_translation.clone()]
+                .into_iter()
+                .chain(translation_info.other_translations.iter().cloned())
+                .flat_map(|t| {
+                    let meanings: Vec<String> = t.split([',', ';']).map(String::from).collect();
+                    [t.clone(), without_parentheses(&t)].into_iter().chain(meanings)
+                })
+                .collect(),
+        };
+        answers
+            .iter()
+            .map(|a| normalize_answer(a))
+            .filter(|a| !a.is_empty())
+            .collect()
+    }
+
+    fn grade(&self, answer: &str) -> Grade {
+        let answer = normalize_answer(answer);
+        // english verbs may be typed with their `to`
+        let answer = match self.direction {
+            Direction::Production => answer,
+            _ => answer.strip_prefix("to ").map(String::from).unwrap_or(answer),
+        };
+        if answer.is_empty() {
+            return Grade::Wrong;
+        }
+        let expected = self.expected();
+        if expected.contains(&answer) {
+            return Grade::Correct;
+        }
+        let close = expected
+            .iter()
+            .any(|e| e.chars().count() >= 5 && edit_distance(e, &answer) == 1);
+        match close {
+            true => Grade::Close,
+            false => Grade::Wrong,
+        }
+    }
+
+    fn solution(&self) -> String {
+        let translation_info = self.translation_info;
+        let mut solution = format!(
+            "{}: {}",
+            translation_info.title, translation_info.main_translation
+        );
+        if !translation_info.other_translations.is_empty() {
+            solution += &format!(" ({})", translation_info.other_translations_joined());
+        }
+        if let Some(phrase) = translation_info.context_phrases().first() {
+            solution += &format!("\n{phrase}");
+        }
+        solution
+    }
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64
+}
+
+// None once stdin is closed
+fn ask(prompt: &str) -> Result<Option<String>, Box<dyn Error>> {
+    print!("\n{prompt}\n> ");
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    match io::stdin().read_line(&mut answer)? {
+        0 => Ok(None),
+        _ => Ok(Some(answer)),
+    }
+}
+
+// due cards come first, the oldest due first, then up to `n_new` never reviewed ones
+pub fn review(direction: Direction, limit: usize, n_new: usize) -> Result<(), Box<dyn Error>> {
+    let mut storage = Storage::open()?;
+    let mut translation_infos = storage.in_deck()?;
+    let mut seen_search_results = vec![];
+    translation_infos.retain(|t| {
+        let is_new = !seen_search_results.contains(&t.search_result);
+        seen_search_results.push(t.search_result.clone());
+        is_new
+    });
+    let directions = match direction {
+        Direction::Both => vec![Direction::Recognition, Direction::Production],
+        direction => vec![direction],
+    };
+    let now = now();
+    let mut due = vec![];
+    let mut new = vec![];
+    for direction in directions {
+        let states = storage.review_states(direction.name())?;
+        for translation_info in &translation_infos {
+            let key = (
+                translation_info.search_query.clone(),
+                translation_info.search_result.clone(),
+            );
+            let card = |state| Card {
+                translation_info,
+                direction,
+                state,
+                again: false,
+            };
+            match states.get(&key) {
+                Some(state) if state.due <= now => due.push(card(state.clone())),
+                Some(_) => {}
+                None => new.push(card(ReviewState::default())),
+            }
+        }
+    }
+    due.sort_by_key(|card| card.state.due);
+    due.truncate(limit);
+    new.truncate(n_new);
+    if due.is_empty() && new.is_empty() {
+        println!("Nothing to review now.");
+        return Ok(());
+    }
+    println!(
+        "{} to review and {} new, leave empty if you don't know and Ctrl-D to stop.",
+        due.len(),
+        new.len()
+    );
+
+    let mut queue: VecDeque<Card> = due.into_iter().chain(new).collect();
+    let (mut n_correct, mut n_wrong) = (0, 0);
+    while let Some(mut card) = queue.pop_front() {
+        let Some(answer) = ask(&card.prompt())? else {
+            println!();
+            break;
+        };
+        let grade = card.grade(&answer);
+        match grade {
+            Grade::Correct => println!("Correct. {}", card.solution()),
+            Grade::Close => println!("Almost. {}", card.solution()),
+            Grade::Wrong => println!("Wrong. {}", card.solution()),
+        }
+        if !card.again {
+            match grade {
+                Grade::Wrong => n_wrong += 1,
+                _ => n_correct += 1,
+            }
+            card.state.schedule(grade.quality(), now);
+            storage.set_review_state(
+                &card.translation_info.search_query,
+                &card.translation_info.search_result,
+                card.direction.name(),
+                &card.state,
+            )?;
+        }
+        if grade == Grade::Wrong {
+            card.again = true;
+            queue.push_back(card);
+        }
+    }
+    println!(
+        "\nReviewed: {}\nCorrect: {n_correct}\nWrong: {n_wrong}",
+        n_correct + n_wrong
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn card(translation_info: &TranslationInfo, direction: Direction) -> Card<'_> {
+        Card {
+            translation_info,
+            direction,
+            state: ReviewState::default(),
+            again: false,
+        }
+    }
+
+    #[test]
+    fn correct_answers_grow_the_interval() {
+        let mut state = ReviewState::default();
+        let mut intervals = vec![];
+        for _ in 0..4 {
+            state.schedule(Grade::Correct.quality(), 0);
+            intervals.push(state.interval_days);
+        }
+        assert_eq!(intervals, [1, 6, 15, 38]);
+        assert_eq!(state.repetitions, 4);
+        // a quality of 4 keeps the ease where it is
+        assert!((state.ease - 2.5).abs() < 1e-9);
+        assert_eq!(state.due, 38 * SECONDS_PER_DAY);
+    }
+
+    #[test]
+    fn ease_follows_the_quality() {
+        let mut state = ReviewState::default();
+        state.schedule(5, 0);
+        assert!((state.ease - 2.6).abs() < 1e-9);
+        state.schedule(Grade::Close.quality(), 0);
+        assert!((state.ease - 2.46).abs() < 1e-9);
+        assert_eq!(state.interval_days, 6);
+    }
+
+    #[test]
+    fn a_wrong_answer_starts_over() {
+        let mut state = ReviewState::default();
+        for _ in 0..3 {
+            state.schedule(Grade::Correct.quality(), 0);
+        }
+        state.schedule(Grade::Wrong.quality(), 100);
+        assert_eq!(state.interval_days, 1);
+        assert_eq!(state.repetitions, 0);
+        assert_eq!(state.due, 100 + SECONDS_PER_DAY);
+    }
+
+    #[test]
+    fn ease_does_not_go_below_the_floor() {
+        let mut state = ReviewState::default();
+        for _ in 0..10 {
+            state.schedule(Grade::Wrong.quality(), 0);
+        }
+        assert_eq!(state.ease, MIN_EASE);
+    }
+
+    #[test]
+    fn recognition_answers_may_start_with_to() {
+        let translation_info = TranslationInfo::for_test("ре́зать", "cut");
+        let card = card(&translation_info, Direction::Recognition);
+        assert!(card.grade("to cut") == Grade::Correct);
+        assert!(card.grade("Cut!") == Grade::Correct);
+        assert!(card.grade("") == Grade::Wrong);
+    }
+
+    #[test]
+    fn production_answers_ignore_stress_and_yo() {
+        let translation_info = TranslationInfo::for_test("ёлка", "fir tree");
+        let card = card(&translation_info, Direction::Production);
+        assert!(card.grade("елка") == Grade::Correct);
+        assert!(card.grade("ё'лка") == Grade::Correct);
+        assert!(card.grade("to ёлка") == Grade::Wrong);
+    }
+
+    #[test]
+    fn one_letter_off_is_close_for_long_enough_words() {
+        let translation_info = TranslationInfo::for_test("но́ж", "knife");
+        let recognition = card(&translation_info, Direction::Recognition);
+        assert!(recognition.grade("knif") == Grade::Close);
+        assert!(recognition.grade("knifes") == Grade::Close);
+        assert!(recognition.grade("kniv") == Grade::Wrong);
+        // the russian is too short for a typo to pass
+        let production = card(&translation_info, Direction::Production);
+        assert!(production.grade("нош") == Grade::Wrong);
+    }
+
+    #[test]
+    fn each_listed_meaning_is_an_answer() {
+        let mut translation_info = TranslationInfo::for_test("ключ", "key (to a lock)");
+        translation_info.other_translations = vec![String::from("spring; source")];
+        let card = card(&translation_info, Direction::Recognition);
+        assert!(card.grade("key") == Grade::Correct);
+        assert!(card.grade("key to a lock") == Grade::Correct);
+        assert!(card.grade("source") == Grade::Correct);
+    }
+
+    #[test]
+    fn edit_distance_counts_single_letter_edits() {
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("", "нож"), 3);
+        assert_eq!(edit_distance("нож", "нож"), 0);
+        assert_eq!(edit_distance("ножи", "нож"), 1);
+    }
+
+    #[test]
+    fn parentheses_are_removed_with_their_content() {
+        assert_eq!(without_parentheses("key (to a lock)"), "key ");
+        assert_eq!(without_parentheses("a (b (c) d) e"), "a  e");
+        assert_eq!(without_parentheses("knife"), "knife");
+    }
+}
@@ -0,0 +1,26 @@
+use scraper::{ElementRef, Selector};
+
+// all the text inside an element, with whitespace collapsed
+pub fn element_text(element: ElementRef) -> String {
+    element
+        .text()
+        .collect::<Vec<&str>>()
+        .join("")
+        .split_whitespace()
+        .collect::<Vec<&str>>()
+        .join(" ")
+}
+
+// a cell may hold more than one form, e.g. animate and inanimate accusative
+pub fn cell_forms(cell: ElementRef) -> String {
+    let p_selector = Selector::parse("p").unwrap();
+    let forms = cell
+        .select(&p_selector)
+        .map(element_text)
+        .filter(|s| !s.is_empty())
+        .collect::<Vec<String>>();
+    match forms.is_empty() {
+        true => element_text(cell),
+        false => forms.join(" / "),
+    }
+}
@@ -0,0 +1,476 @@
+use crate::anki::note_guid;
+use crate::or::TranslationInfo;
+use crate::review::ReviewState;
+use crate::utils::{get_legacy_csv_path, get_main_db_path};
+use csv::{ReaderBuilder, WriterBuilder};
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use std::collections::HashMap;
+use std::error::Error;
+use std::io::Write;
+use std::path::Path;
+
+// each entry is applied once, in order, and tracked with `PRAGMA user_version`
+const MIGRATIONS: &[&str] = &[r#"
+    CREATE TABLE translation_info (
+        id INTEGER PRIMARY KEY,
+        search_query TEXT NOT NULL,
+        search_result TEXT NOT NULL,
+        title TEXT NOT NULL,
+        main_translation TEXT NOT NULL,
+        other_translations TEXT NOT NULL,
+        overview TEXT NOT NULL,
+        context_phrase TEXT,
+        context_phrase_translation TEXT,
+        user_context_phrase TEXT,
+        created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now'))
+    );
+    CREATE UNIQUE INDEX translation_info_search_query ON translation_info (search_query);
+    CREATE INDEX translation_info_search_result ON translation_info (search_result);
+"#, r#"
+    ALTER TABLE translation_info ADD COLUMN declension TEXT;
+"#, r#"
+    ALTER TABLE translation_info ADD COLUMN verb TEXT;
+"#, r#"
+    ALTER TABLE translation_info ADD COLUMN sentences TEXT NOT NULL DEFAULT '[]';
+"#, r#"
+    DROP INDEX translation_info_search_query;
+    CREATE UNIQUE INDEX translation_info_search_query_result ON translation_info (search_query, search_result);
+"#, r#"
+    ALTER TABLE translation_info ADD COLUMN audio_url TEXT;
+    ALTER TABLE translation_info ADD COLUMN audio_file TEXT;
+"#, r#"
+    ALTER TABLE translation_info ADD COLUMN guid TEXT;
+"#, r#"
+    ALTER TABLE translation_info ADD COLUMN source TEXT;
+"#, r#"
+    ALTER TABLE translation_info ADD COLUMN in_deck INTEGER NOT NULL DEFAULT 1;
+"#, r#"
+    CREATE TABLE review_state (
+        search_query TEXT NOT NULL,
+        search_result TEXT NOT NULL,
+        direction TEXT NOT NULL,
+        ease REAL NOT NULL,
+        interval_days INTEGER NOT NULL,
+        repetitions INTEGER NOT NULL,
+        due INTEGER NOT NULL,
+        PRIMARY KEY (search_query, search_result, direction)
+    );
+"#];
+
+// index of the migration adding `guid`, entries saved before it get theirs right after
+const GUID_MIGRATION: usize = 6;
+
+const CSV_HEADER: [&str; 9] = [
+    "search_query",
+    "search_result",
+    "title",
+    "main_translation",
+    "other_translations",
+    "overview",
+    "context_phrase",
+    "context_phrase_translation",
+    "user_context_phrase",
+];
+
+const SELECT_COLUMNS: &str = "search_query, search_result, title, main_translation, other_translations, overview, context_phrase, context_phrase_translation, user_context_phrase, declension, verb, sentences, audio_url, audio_file, guid, source";
+
+pub struct Storage {
+    conn: Connection,
+}
+
+impl Storage {
+    pub fn open() -> Result<Self, Box<dyn Error>> {
+        let db_path = get_main_db_path()?;
+        let is_new = !db_path.is_file();
+        let mut storage = Self::open_path(&db_path)?;
+        if is_new {
+            if let Some(csv_path) = get_legacy_csv_path()? {
+                let n_imported = storage.import_csv(&csv_path)?;
+                // stdout may be the json or plain output of a search
+                eprintln!("Imported {n_imported} entries from {}.", csv_path.display());
+            }
+        }
+        Ok(storage)
+    }
+
+    pub fn open_path(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let mut storage = Self {
+            conn: Connection::open(path)?,
+        };
+        storage.migrate()?;
+        Ok(storage)
+    }
+
+    fn migrate(&mut self) -> Result<(), Box<dyn Error>> {
+        let version: usize = self
+            .conn
+            .query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        if version >= MIGRATIONS.len() {
+            return Ok(());
+        }
+        let tx = self.conn.transaction()?;
+        for (i, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+            tx.execute_batch(migration)?;
+            if i == GUID_MIGRATION {
+                Self::fill_missing_guids(&tx)?;
+            }
+            tx.pragma_update(None, "user_version", &(i as i64 + 1))?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    // entries saved before guids existed get theirs derived the same way as new ones
+    fn fill_missing_guids(conn: &Connection) -> Result<(), Box<dyn Error>> {
+        let mut statement =
+            conn.prepare("SELECT id, search_result FROM translation_info WHERE guid IS NULL")?;
+        let rows = statement
+            .query_map([], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?)))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        for (id, search_result) in rows {
+            conn.execute(
+                "UPDATE translation_info SET guid = ? WHERE id = ?",
+                params![note_guid(&search_result), id],
+            )?;
+        }
+        Ok(())
+    }
+
+    fn translation_info_from_row(row: &Row) -> rusqlite::Result<TranslationInfo> {
+        let other_translations: String = row.get(4)?;
+        Ok(TranslationInfo {
+            search_query: row.get(0)?,
+            search_result: row.get(1)?,
+            title: row.get(2)?,
+            main_translation: row.get(3)?,
+            other_translations: serde_json::from_str(&other_translations).unwrap_or_default(),
+            overview: row.get(5)?,
+            context_phrase: row.get(6)?,
+            context_phrase_translation: row.get(7)?,
+            user_context_phrase: row.get(8)?,
+            declension: row
+                .get::<_, Option<String>>(9)?
+                .and_then(|d| serde_json::from_str(&d).ok()),
+            verb: row
+                .get::<_, Option<String>>(10)?
+                .and_then(|v| serde_json::from_str(&v).ok()),
+            sentences: serde_json::from_str(&row.get::<_, String>(11)?).unwrap_or_default(),
+            audio_url: row.get(12)?,
+            audio_file: row.get(13)?,
+            guid: row.get(14)?,
+            source: row.get(15)?,
+        })
+    }
+
+    pub fn get_by_query(&self, search_query: &str) -> Result<Option<TranslationInfo>, Box<dyn Error>> {
+        Ok(self
+            .conn
+            .query_row(
+                &format!("SELECT {SELECT_COLUMNS} FROM translation_info WHERE search_query = ? ORDER BY id LIMIT 1"),
+                [search_query],
+                Self::translation_info_from_row,
+            )
+            .optional()?)
+    }
+
+    pub fn get_by_query_and_result(
+        &self,
+        search_query: &str,
+        search_result: &str,
+    ) -> Result<Option<TranslationInfo>, Box<dyn Error>> {
+        Ok(self
+            .conn
+            .query_row(
+                &format!("SELECT {SELECT_COLUMNS} FROM translation_info WHERE search_query = ? AND search_result = ?"),
+                [search_query, search_result],
+                Self::translation_info_from_row,
+            )
+            .optional()?)
+    }
+
+    pub fn all(&self) -> Result<Vec<TranslationInfo>, Box<dyn Error>> {
+        let mut statement = self
+            .conn
+            .prepare(&format!("SELECT {SELECT_COLUMNS} FROM translation_info ORDER BY id"))?;
+        let rows = statement.query_map([], Self::translation_info_from_row)?;
+        Ok(rows.collect::<rusqlite::Result<Vec<TranslationInfo>>>()?)
+    }
+
+    // the entries that are not left out of the deck
+    pub fn in_deck(&self) -> Result<Vec<TranslationInfo>, Box<dyn Error>> {
+        let mut statement = self.conn.prepare(&format!(
+            "SELECT {SELECT_COLUMNS} FROM translation_info WHERE in_deck ORDER BY id"
+        ))?;
+        let rows = statement.query_map([], Self::translation_info_from_row)?;
+        Ok(rows.collect::<rusqlite::Result<Vec<TranslationInfo>>>()?)
+    }
+
+    // every entry, and whether it goes into the deck
+    pub fn all_with_in_deck(&self) -> Result<Vec<(TranslationInfo, bool)>, Box<dyn Error>> {
+        let mut statement = self.conn.prepare(&format!(
+            "SELECT {SELECT_COLUMNS}, in_deck FROM translation_info ORDER BY id"
+        ))?;
+        let rows = statement.query_map([], |row| {
+            Ok((Self::translation_info_from_row(row)?, row.get(16)?))
+        })?;
+        Ok(rows.collect::<rusqlite::Result<Vec<(TranslationInfo, bool)>>>()?)
+    }
+
+    // `since` and `until` are YYYY-MM-DD dates, both inclusive
+    pub fn saved_between(
+        &self,
+        since: Option<&str>,
+        until: Option<&str>,
+    ) -> Result<Vec<TranslationInfo>, Box<dyn Error>> {
+        let mut statement = self.conn.prepare(&format!(
+            "SELECT {SELECT_COLUMNS} FROM translation_info \
+             WHERE (?1 IS NULL OR created_at >= CAST(strftime('%s', ?1) AS INTEGER)) \
+             AND (?2 IS NULL OR created_at < CAST(strftime('%s', ?2, '+1 day') AS INTEGER)) \
+             ORDER BY id"
+        ))?;
+        let rows = statement.query_map(params![since, until], Self::translation_info_from_row)?;
+        Ok(rows.collect::<rusqlite::Result<Vec<TranslationInfo>>>()?)
+    }
+
+    // returns false if an entry for the same search query and result already existed
+    pub fn insert(&mut self, translation_info: &TranslationInfo) -> Result<bool, Box<dyn Error>> {
+        let tx = self.conn.transaction()?;
+        let inserted = Self::insert_with(&tx, translation_info)?;
+        tx.commit()?;
+        Ok(inserted)
+    }
+
+    fn insert_with(
+        conn: &Connection,
+        translation_info: &TranslationInfo,
+    ) -> Result<bool, Box<dyn Error>> {
+        let n_changed = conn.execute(
+            "INSERT OR IGNORE INTO translation_info (search_query, search_result, title, main_translation, other_translations, overview, context_phrase, context_phrase_translation, user_context_phrase, declension, verb, sentences, audio_url, audio_file, guid, source) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+            params![
+                translation_info.search_query,
+                translation_info.search_result,
+                translation_info.title,
+                translation_info.main_translation,
+                serde_json::to_string(&translation_info.other_translations)?,
+                translation_info.overview,
+                translation_info.context_phrase,
+                translation_info.context_phrase_translation,
+                translation_info.user_context_phrase,
+                translation_info
+                    .declension
+                    .as_ref()
+                    .map(serde_json::to_string)
+                    .transpose()?,
+                translation_info
+                    .verb
+                    .as_ref()
+                    .map(serde_json::to_string)
+                    .transpose()?,
+                serde_json::to_string(&translation_info.sentences)?,
+                translation_info.audio_url,
+                translation_info.audio_file,
+                translation_info.guid,
+                translation_info.source,
+            ],
+        )?;
+        Ok(n_changed > 0)
+    }
+
+    // replaces the saved fields of the entry with the same search query and result
+    pub fn update(&mut self, translation_info: &TranslationInfo) -> Result<(), Box<dyn Error>> {
+        self.conn.execute(
+            "UPDATE translation_info SET title = ?, main_translation = ?, other_translations = ?, overview = ?, context_phrase = ?, context_phrase_translation = ?, user_context_phrase = ?, declension = ?, verb = ?, sentences = ?, audio_url = ?, audio_file = ?, source = ? WHERE search_query = ? AND search_result = ?",
+            params![
+                translation_info.title,
+                translation_info.main_translation,
+                serde_json::to_string(&translation_info.other_translations)?,
+                translation_info.overview,
+                translation_info.context_phrase,
+                translation_info.context_phrase_translation,
+                translation_info.user_context_phrase,
+                translation_info
+                    .declension
+                    .as_ref()
+                    .map(serde_json::to_string)
+                    .transpose()?,
+                translation_info
+                    .verb
+                    .as_ref()
+                    .map(serde_json::to_string)
+                    .transpose()?,
+                serde_json::to_string(&translation_info.sentences)?,
+                translation_info.audio_url,
+                translation_info.audio_file,
+                translation_info.source,
+                translation_info.search_query,
+                translation_info.search_result,
+            ],
+        )?;
+        Ok(())
+    }
+
+    pub fn set_in_deck(
+        &mut self,
+        search_query: &str,
+        search_result: &str,
+        in_deck: bool,
+    ) -> Result<(), Box<dyn Error>> {
+        self.conn.execute(
+            "UPDATE translation_info SET in_deck = ? WHERE search_query = ? AND search_result = ?",
+            params![in_deck, search_query, search_result],
+        )?;
+        Ok(())
+    }
+
+    // marks every entry for the search queries as coming from `source`
+    pub fn set_source(&mut self, search_queries: &[String], source: &str) -> Result<(), Box<dyn Error>> {
+        let tx = self.conn.transaction()?;
+        for search_query in search_queries {
+            tx.execute(
+                "UPDATE translation_info SET source = ? WHERE search_query = ?",
+                [source, search_query],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    // copies the entries of `source` missing here, keeping when they were first saved
+    pub fn merge_from(&mut self, source: &Storage) -> Result<usize, Box<dyn Error>> {
+        let mut statement = source.conn.prepare(&format!(
+            "SELECT {SELECT_COLUMNS}, created_at FROM translation_info ORDER BY id"
+        ))?;
+        let rows = statement.query_map([], |row| {
+            Ok((Self::translation_info_from_row(row)?, row.get(16)?))
+        })?;
+        let entries = rows.collect::<rusqlite::Result<Vec<(TranslationInfo, i64)>>>()?;
+        let tx = self.conn.transaction()?;
+        let mut n_inserted = 0;
+        for (translation_info, created_at) in &entries {
+            if Self::insert_with(&tx, translation_info)? {
+                tx.execute(
+                    "UPDATE translation_info SET created_at = ? WHERE search_query = ? AND search_result = ?",
+                    params![created_at, translation_info.search_query, translation_info.search_result],
+                )?;
+                n_inserted += 1;
+            }
+        }
+        tx.commit()?;
+        Ok(n_inserted)
+    }
+
+    pub fn count(&self) -> Result<usize, Box<dyn Error>> {
+        Ok(self
+            .conn
+            .query_row("SELECT COUNT(*) FROM translation_info", [], |row| row.get(0))?)
+    }
+
+    // removes every entry for the search query, returns false if there was none
+    pub fn remove(&mut self, search_query: &str) -> Result<bool, Box<dyn Error>> {
+        let n_changed = self
+            .conn
+            .execute("DELETE FROM translation_info WHERE search_query = ?", [search_query])?;
+        self.conn
+            .execute("DELETE FROM review_state WHERE search_query = ?", [search_query])?;
+        Ok(n_changed > 0)
+    }
+
+    // removes only the entry for the search query and result, returns false if there was none
+    pub fn remove_entry(&mut self, search_query: &str, search_result: &str) -> Result<bool, Box<dyn Error>> {
+        let n_changed = self.conn.execute(
+            "DELETE FROM translation_info WHERE search_query = ? AND search_result = ?",
+            [search_query, search_result],
+        )?;
+        self.conn.execute(
+            "DELETE FROM review_state WHERE search_query = ? AND search_result = ?",
+            [search_query, search_result],
+        )?;
+        Ok(n_changed > 0)
+    }
+
+    // scheduling of every reviewed entry in the direction, by search query and result
+    pub fn review_states(
+        &self,
+        direction: &str,
+    ) -> Result<HashMap<(String, String), ReviewState>, Box<dyn Error>> {
+        let mut statement = self.conn.prepare(
+            "SELECT search_query, search_result, ease, interval_days, repetitions, due FROM review_state WHERE direction = ?",
+        )?;
+        let rows = statement.query_map([direction], |row| {
+            Ok((
+                (row.get(0)?, row.get(1)?),
+                ReviewState {
+                    ease: row.get(2)?,
+                    interval_days: row.get(3)?,
+                    repetitions: row.get(4)?,
+                    due: row.get(5)?,
+                },
+            ))
+        })?;
+        Ok(rows.collect::<rusqlite::Result<HashMap<_, _>>>()?)
+    }
+
+    pub fn set_review_state(
+        &mut self,
+        search_query: &str,
+        search_result: &str,
+        direction: &str,
+        state: &ReviewState,
+    ) -> Result<(), Box<dyn Error>> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO review_state (search_query, search_result, direction, ease, interval_days, repetitions, due) VALUES (?, ?, ?, ?, ?, ?, ?)",
+            params![
+                search_query,
+                search_result,
+                direction,
+                state.ease,
+                state.interval_days,
+                state.repetitions,
+                state.due,
+            ],
+        )?;
+        Ok(())
+    }
+
+    // imports every row of an old pipe-delimited main.csv, returns the number of new entries
+    pub fn import_csv(&mut self, csv_path: &Path) -> Result<usize, Box<dyn Error>> {
+        let mut reader = ReaderBuilder::new()
+            .delimiter(b'|')
+            .flexible(true)
+            .from_path(csv_path)?;
+        let tx = self.conn.transaction()?;
+        let mut n_imported = 0;
+        for record in reader.records() {
+            let translation_info = TranslationInfo::from_csv_string_record_slice(record?)?;
+            if Self::insert_with(&tx, &translation_info)? {
+                n_imported += 1;
+            }
+        }
+        tx.commit()?;
+        Ok(n_imported)
+    }
+
+    // writes the entries in the format read by `import_csv`
+    pub fn export_csv<W: Write>(translation_infos: &[TranslationInfo], writer: W) -> Result<(), Box<dyn Error>> {
+        let mut writer = WriterBuilder::new().delimiter(b'|').from_writer(writer);
+        writer.write_record(CSV_HEADER)?;
+        for translation_info in translation_infos {
+            writer.write_record([
+                translation_info.search_query.as_str(),
+                translation_info.search_result.as_str(),
+                translation_info.title.as_str(),
+                translation_info.main_translation.as_str(),
+                translation_info.other_translations_joined().as_str(),
+                translation_info.overview_in_one_line().as_str(),
+                translation_info.context_phrase.as_deref().unwrap_or(""),
+                translation_info
+                    .context_phrase_translation
+                    .as_deref()
+                    .unwrap_or(""),
+                translation_info.user_context_phrase.as_deref().unwrap_or(""),
+            ])?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,62 @@
+// combining stress marks take no room in the terminal
+pub fn display_width(s: &str) -> usize {
+    s.chars().filter(|c| *c != '\u{301}').count()
+}
+
+fn pad(s: &str, width: usize) -> String {
+    format!("{s}{}", " ".repeat(width.saturating_sub(display_width(s))))
+}
+
+fn border(widths: &[usize], left: char, middle: char, right: char) -> String {
+    let cells = widths
+        .iter()
+        .map(|w| "\u{2500}".repeat(w + 2))
+        .collect::<Vec<String>>()
+        .join(&middle.to_string());
+    format!("{left}{cells}{right}")
+}
+
+// first row is the header, every row must have the same length
+pub fn box_table(rows: &[Vec<String>]) -> String {
+    let n_columns = rows.first().map(|r| r.len()).unwrap_or(0);
+    let widths = (0..n_columns)
+        .map(|i| rows.iter().map(|r| display_width(&r[i])).max().unwrap_or(0))
+        .collect::<Vec<usize>>();
+    let mut lines = vec![border(&widths, '\u{250c}', '\u{252c}', '\u{2510}')];
+    for (i, row) in rows.iter().enumerate() {
+        let cells = row
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!(" {} ", pad(cell, *width)))
+            .collect::<Vec<String>>()
+            .join("\u{2502}");
+        lines.push(format!("\u{2502}{cells}\u{2502}"));
+        if i == 0 && rows.len() > 1 {
+            lines.push(border(&widths, '\u{251c}', '\u{253c}', '\u{2524}'));
+        }
+    }
+    lines.push(border(&widths, '\u{2514}', '\u{2534}', '\u{2518}'));
+    lines.join("\n")
+}
+
+pub fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+// first row and first column are headers
+pub fn html_table(rows: &[Vec<String>]) -> String {
+    let mut html = String::from("<table>");
+    for (i, row) in rows.iter().enumerate() {
+        html.push_str("<tr>");
+        for (j, cell) in row.iter().enumerate() {
+            let tag = if i == 0 || j == 0 { "th" } else { "td" };
+            html.push_str(&format!("<{tag}>{}</{tag}>", escape_html(cell)));
+        }
+        html.push_str("</tr>");
+    }
+    html.push_str("</table>");
+    html
+}
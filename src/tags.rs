@@ -0,0 +1,67 @@
+use crate::or::TranslationInfo;
+use regex::Regex;
+
+const PARTS_OF_SPEECH: [&str; 11] = [
+    "noun",
+    "verb",
+    "adjective",
+    "adverb",
+    "pronoun",
+    "preposition",
+    "conjunction",
+    "particle",
+    "numeral",
+    "interjection",
+    "expression",
+];
+const GENDERS: [&str; 3] = ["masculine", "feminine", "neuter"];
+// "imperfective" has to be checked first, as it contains "perfective"
+const ASPECTS: [&str; 2] = ["imperfective", "perfective"];
+
+fn overview_words(translation_info: &TranslationInfo) -> Vec<String> {
+    translation_info
+        .overview
+        .to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+pub fn part_of_speech(translation_info: &TranslationInfo) -> Option<&'static str> {
+    let words = overview_words(translation_info);
+    PARTS_OF_SPEECH
+        .into_iter()
+        .find(|p| words.iter().any(|w| w == p))
+        .or(translation_info.verb.as_ref().map(|_| "verb"))
+}
+
+// "top 3,000" becomes "top_3000"
+fn frequency_band(translation_info: &TranslationInfo) -> Option<String> {
+    let re = Regex::new(r"(?i)top ([\d,]+)").unwrap();
+    let band = re.captures(&translation_info.overview)?[1].replace(',', "");
+    Some(format!("top_{band}"))
+}
+
+// anki tags can't have spaces
+fn sanitize_tag(tag: &str) -> String {
+    tag.split_whitespace().collect::<Vec<&str>>().join("_")
+}
+
+// part of speech, gender, aspect and frequency band from the overview, plus the source
+pub fn tags(translation_info: &TranslationInfo) -> Vec<String> {
+    let words = overview_words(translation_info);
+    let has_word = |w: &&str| words.iter().any(|x| x == w);
+    let mut tags = vec![];
+    tags.extend(part_of_speech(translation_info).map(String::from));
+    tags.extend(GENDERS.into_iter().filter(has_word).map(String::from));
+    let aspect = translation_info
+        .verb
+        .as_ref()
+        .and_then(|v| v.aspect.clone())
+        .or(ASPECTS.into_iter().find(has_word).map(String::from));
+    tags.extend(aspect);
+    tags.extend(frequency_band(translation_info));
+    tags.extend(translation_info.source.as_deref().map(sanitize_tag));
+    tags
+}
@@ -0,0 +1,180 @@
+use crate::anki::{cloze_template, model_templates, CLOZE_FIELD_NAMES, FIELD_NAMES};
+use crate::utils::{get_style_css_path, get_templates_dir};
+use regex::Regex;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+// anki's own fields, usable in any template
+const SPECIAL_FIELDS: [&str; 7] = ["FrontSide", "Tags", "Type", "Deck", "Subdeck", "Card", "CardFlag"];
+const DEFAULT_SET_NAME: &str = "default";
+
+pub struct CardTemplate {
+    pub name: &'static str,
+    pub q_format: String,
+    pub a_format: String,
+}
+
+// the templates and css the notes are made with, the built-in ones fill in what a set doesn't have
+pub struct TemplateSet {
+    pub cards: Vec<CardTemplate>,
+    pub cloze: CardTemplate,
+    pub css: Option<String>,
+}
+
+// "Card 1" is read from card.front.html and card.back.html, "Recognition" from recognition.*
+fn file_stem(template_name: &str) -> String {
+    match template_name {
+        "Card 1" => String::from("card"),
+        name => name.to_lowercase(),
+    }
+}
+
+fn read_or(dir: Option<&Path>, file_name: &str, builtin: &str) -> Result<String, Box<dyn Error>> {
+    match dir.map(|d| d.join(file_name)).filter(|p| p.is_file()) {
+        Some(p) => Ok(std::fs::read_to_string(p)?),
+        None => Ok(builtin.to_string()),
+    }
+}
+
+fn load_card_template(
+    dir: Option<&Path>,
+    (name, q_format, a_format): (&'static str, &str, &str),
+) -> Result<CardTemplate, Box<dyn Error>> {
+    let stem = file_stem(name);
+    Ok(CardTemplate {
+        name,
+        q_format: read_or(dir, &format!("{stem}.front.html"), q_format)?,
+        a_format: read_or(dir, &format!("{stem}.back.html"), a_format)?,
+    })
+}
+
+// names of the fields a template uses, without `#`, `^`, `/` or filters like `type:`
+fn referenced_fields(template: &str) -> Vec<String> {
+    let re = Regex::new(r"\{\{([^}]*)\}\}").unwrap();
+    re.captures_iter(template)
+        .map(|c| c[1].trim().trim_start_matches(['#', '^', '/']).to_string())
+        .map(|f| f.rsplit(':').next().unwrap().trim().to_string())
+        .filter(|f| !f.is_empty() && !SPECIAL_FIELDS.contains(&f.as_str()))
+        .collect()
+}
+
+fn check_card_template(card: &CardTemplate, field_names: &[&str]) -> Result<(), String> {
+    let stem = file_stem(card.name);
+    for (side, template) in [("front", &card.q_format), ("back", &card.a_format)] {
+        let unknown = referenced_fields(template)
+            .into_iter()
+            .filter(|f| !field_names.contains(&f.as_str()))
+            .collect::<Vec<String>>();
+        if !unknown.is_empty() {
+            return Err(format!(
+                "{stem}.{side}.html uses unknown fields: {}.",
+                unknown.join(", ")
+            ));
+        }
+    }
+    // optional cards are only generated when their front field isn't empty, anki makes the
+    // card as soon as any field on the front has a value, so the others must be in its section
+    let front_field = format!("{stem}_front");
+    if field_names.contains(&front_field.as_str()) {
+        if !referenced_fields(&card.q_format).contains(&front_field) {
+            return Err(format!("{stem}.front.html must use {{{{{front_field}}}}}."));
+        }
+        let section_re = Regex::new(&format!(
+            r"(?s)\{{\{{#\s*{front_field}\s*\}}\}}.*?\{{\{{/\s*{front_field}\s*\}}\}}"
+        ))
+        .unwrap();
+        let outside = referenced_fields(&section_re.replace_all(&card.q_format, ""));
+        if !outside.is_empty() {
+            return Err(format!(
+                "{stem}.front.html uses {} outside of {{{{#{front_field}}}}}...{{{{/{front_field}}}}}.",
+                outside.join(", ")
+            ));
+        }
+    }
+    Ok(())
+}
+
+impl TemplateSet {
+    fn load_dir(dir: Option<&Path>) -> Result<Self, Box<dyn Error>> {
+        let cards = model_templates()
+            .into_iter()
+            .map(|t| load_card_template(dir, t))
+            .collect::<Result<Vec<CardTemplate>, Box<dyn Error>>>()?;
+        let cloze = load_card_template(dir, cloze_template())?;
+        for card in &cards {
+            check_card_template(card, &FIELD_NAMES)?;
+        }
+        check_card_template(&cloze, &CLOZE_FIELD_NAMES)?;
+
+        let css_path = match dir.map(|d| d.join("style.css")).filter(|p| p.is_file()) {
+            Some(p) => Some(p),
+            None => get_style_css_path()?,
+        };
+        let css = match css_path {
+            Some(p) => Some(std::fs::read_to_string(p)?),
+            None => {
+                println!("No css found.");
+                None
+            }
+        };
+        Ok(Self { cards, cloze, css })
+    }
+
+    // `name` is a directory inside the templates dir, or a path to one
+    pub fn load(name: Option<&str>) -> Result<Self, Box<dyn Error>> {
+        let templates_dir = get_templates_dir()?;
+        let dir = match name {
+            None => Some(templates_dir.join(DEFAULT_SET_NAME)).filter(|d| d.is_dir()),
+            Some(name) => {
+                let dir = match name.contains(std::path::MAIN_SEPARATOR) {
+                    true => PathBuf::from(name),
+                    false => templates_dir.join(name),
+                };
+                if !dir.is_dir() {
+                    return Err(format!(
+                        "No template set `{name}` in {}.",
+                        templates_dir.display()
+                    )
+                    .into());
+                }
+                Some(dir)
+            }
+        };
+        Self::load_dir(dir.as_deref()).map_err(|e| match &dir {
+            Some(d) => format!("Invalid template set {}: {e}", d.display()).into(),
+            None => e,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn production(q_format: &str) -> CardTemplate {
+        CardTemplate {
+            name: "Production",
+            q_format: q_format.to_string(),
+            a_format: String::from("{{FrontSide}}"),
+        }
+    }
+
+    #[test]
+    fn built_in_templates_pass_the_check() {
+        for template in model_templates() {
+            let card = load_card_template(None, template).unwrap();
+            assert_eq!(check_card_template(&card, &FIELD_NAMES), Ok(()));
+        }
+    }
+
+    #[test]
+    fn optional_fronts_must_be_inside_their_section() {
+        let gated = "{{#production_front}}{{production_front}}{{type:search_result}}{{/production_front}}";
+        assert_eq!(check_card_template(&production(gated), &FIELD_NAMES), Ok(()));
+        let ungated = "{{production_front}}{{type:search_result}}";
+        assert!(check_card_template(&production(ungated), &FIELD_NAMES).is_err());
+        let after_section = "{{#production_front}}{{production_front}}{{/production_front}}{{title}}";
+        assert!(check_card_template(&production(after_section), &FIELD_NAMES).is_err());
+        assert!(check_card_template(&production("{{title}}"), &FIELD_NAMES).is_err());
+    }
+}
@@ -1,16 +1,20 @@
+use crate::collection::{collection_dir, DEFAULT_COLLECTION};
+use crate::config;
 use std::error::Error;
-use std::io::prelude::*;
 use std::path;
 
 pub fn get_or_crate_data_dir() -> Result<path::PathBuf, Box<dyn Error>> {
-    let dir_path = dirs::data_dir().unwrap().join("oraki/");
+    let dir_path = config::get().data_dir.clone();
     if !dir_path.is_dir() {
-        std::fs::create_dir(&dir_path)?;
+        std::fs::create_dir_all(&dir_path)?;
     }
     Ok(dir_path)
 }
 
 pub fn get_main_output_anki_path() -> Result<path::PathBuf, Box<dyn Error>> {
+    if let Some(file_path) = &config::get().output {
+        return Ok(file_path.clone());
+    }
     let dir_path = get_or_crate_data_dir()?;
     let file_path = dir_path.join("output.apkg");
     Ok(file_path)
@@ -25,13 +29,62 @@ pub fn get_style_css_path() -> Result<Option<path::PathBuf>, Box<dyn Error>> {
     Ok(Some(file_path))
 }
 
-pub fn get_main_csv_path() -> Result<path::PathBuf, Box<dyn Error>> {
+// every template set is a directory inside it
+pub fn get_templates_dir() -> Result<path::PathBuf, Box<dyn Error>> {
+    let dir_path = get_or_crate_data_dir()?;
+    Ok(dir_path.join("templates/"))
+}
+
+// the data dir itself for the default collection
+pub fn get_collection_dir() -> Result<path::PathBuf, Box<dyn Error>> {
+    let data_dir = get_or_crate_data_dir()?;
+    let name = config::get().collection.as_deref().unwrap_or(DEFAULT_COLLECTION);
+    Ok(collection_dir(&data_dir, name))
+}
+
+pub fn get_main_db_path() -> Result<path::PathBuf, Box<dyn Error>> {
+    let dir_path = get_collection_dir()?;
+    Ok(dir_path.join("main.db"))
+}
+
+// main.csv was used as storage before main.db, only read to import it into the default collection
+pub fn get_legacy_csv_path() -> Result<Option<path::PathBuf>, Box<dyn Error>> {
+    if config::get().collection.is_some() {
+        return Ok(None);
+    }
     let dir_path = get_or_crate_data_dir()?;
     let file_path = dir_path.join("main.csv");
     if !file_path.is_file() {
-        let header_string = "search_query|search_result|title|main_translation|other_translations|overview|context_phrase|context_phrase_translation\n";
-        let mut file = std::fs::File::create(&file_path)?;
-        file.write_all(header_string.as_bytes())?;
+        return Ok(None);
     }
-    Ok(file_path)
+    Ok(Some(file_path))
+}
+
+// built by `oraki db import`, shared by every collection
+pub fn get_offline_db_path() -> Result<path::PathBuf, Box<dyn Error>> {
+    let dir_path = get_or_crate_data_dir()?;
+    Ok(dir_path.join("offline.db"))
+}
+
+// one checkpoint per list file, so that batch runs over different files don't clobber each other
+pub fn get_checkpoint_dir() -> Result<path::PathBuf, Box<dyn Error>> {
+    let dir_path = get_or_crate_data_dir()?.join("checkpoints/");
+    if !dir_path.is_dir() {
+        std::fs::create_dir(&dir_path)?;
+    }
+    Ok(dir_path)
+}
+
+// lines typed in `oraki repl`, kept between sessions
+pub fn get_repl_history_path() -> Result<path::PathBuf, Box<dyn Error>> {
+    let dir_path = get_or_crate_data_dir()?;
+    Ok(dir_path.join("repl.history"))
+}
+
+pub fn get_media_dir() -> Result<path::PathBuf, Box<dyn Error>> {
+    let dir_path = get_or_crate_data_dir()?.join("media/");
+    if !dir_path.is_dir() {
+        std::fs::create_dir(&dir_path)?;
+    }
+    Ok(dir_path)
 }
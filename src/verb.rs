@@ -0,0 +1,153 @@
+use crate::scrape::{cell_forms, element_text};
+use crate::table::{box_table, html_table};
+use scraper::{ElementRef, Html, Selector};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct VerbForm {
+    pub label: String,
+    pub form: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Verb {
+    pub aspect: Option<String>,
+    // stressed, as shown on the page
+    pub aspect_partner: Option<String>,
+    // present for imperfective verbs, future for perfective ones; я, ты, он/она/оно, мы, вы, они
+    pub conjugation: Vec<VerbForm>,
+    // masculine, feminine, neuter, plural
+    pub past: Vec<VerbForm>,
+    // singular, plural
+    pub imperative: Vec<VerbForm>,
+}
+
+// both `label | form` rows and a header row of labels over a row of forms are used
+fn table_forms(table: ElementRef) -> Vec<VerbForm> {
+    let header_selector = Selector::parse("thead th").unwrap();
+    let row_selector = Selector::parse("tbody tr").unwrap();
+    let th_selector = Selector::parse("th").unwrap();
+    let td_selector = Selector::parse("td").unwrap();
+
+    let rows = table.select(&row_selector).collect::<Vec<ElementRef>>();
+    let labels = table
+        .select(&header_selector)
+        .map(element_text)
+        .collect::<Vec<String>>();
+    if rows.len() == 1 && !labels.is_empty() {
+        return labels
+            .into_iter()
+            .zip(rows[0].select(&td_selector).map(cell_forms))
+            .map(|(label, form)| VerbForm { label, form })
+            .collect();
+    }
+    rows.into_iter()
+        .filter_map(|row| {
+            let mut cells = row.select(&th_selector).chain(row.select(&td_selector));
+            let label = element_text(cells.next()?);
+            let form = cell_forms(cells.next()?);
+            Some(VerbForm { label, form })
+        })
+        .collect()
+}
+
+fn section_forms(document: &Html, selector_str: &str) -> Vec<VerbForm> {
+    let selector = Selector::parse(selector_str).unwrap();
+    document
+        .select(&selector)
+        .next()
+        .map(table_forms)
+        .unwrap_or_default()
+}
+
+// the overview reads like "verb, imperfective" and "perfective: сде́лать"
+fn aspect_and_partner(document: &Html) -> (Option<String>, Option<String>) {
+    let p_selector = Selector::parse(".overview p").unwrap();
+    let a_selector = Selector::parse("a").unwrap();
+    let mut aspect = None;
+    let mut aspect_partner = None;
+    for p in document.select(&p_selector) {
+        let text = element_text(p);
+        let link = p.select(&a_selector).next();
+        match link {
+            Some(a) if text.contains("perfective") && aspect_partner.is_none() => {
+                aspect_partner = Some(element_text(a));
+            }
+            None if aspect.is_none() => {
+                aspect = ["imperfective", "perfective"]
+                    .into_iter()
+                    .find(|a| text.split(", ").any(|word| word == *a))
+                    .map(String::from);
+            }
+            _ => (),
+        }
+    }
+    (aspect, aspect_partner)
+}
+
+impl Verb {
+    // None for anything that is not a verb
+    pub fn from_response_text(response_text: &str) -> Option<Self> {
+        let document = Html::parse_document(response_text);
+        let conjugation = section_forms(&document, ".conjugation table");
+        if conjugation.is_empty() {
+            return None;
+        }
+        let (aspect, aspect_partner) = aspect_and_partner(&document);
+        Some(Self {
+            aspect,
+            aspect_partner,
+            conjugation,
+            past: section_forms(&document, ".past table"),
+            imperative: section_forms(&document, ".imperative table"),
+        })
+    }
+
+    fn tables(&self) -> Vec<Vec<Vec<String>>> {
+        let tense = match self.aspect.as_deref() {
+            Some("perfective") => "future",
+            Some("imperfective") => "present",
+            _ => "",
+        };
+        [
+            (tense, &self.conjugation),
+            ("past", &self.past),
+            ("imperative", &self.imperative),
+        ]
+        .into_iter()
+        .filter(|(_, forms)| !forms.is_empty())
+        .map(|(title, forms)| {
+            let mut rows = vec![vec![String::new(), String::from(title)]];
+            for f in forms.iter() {
+                rows.push(vec![f.label.clone(), f.form.clone()]);
+            }
+            rows
+        })
+        .collect()
+    }
+
+    pub fn to_box_tables(&self) -> String {
+        let mut lines = self
+            .tables()
+            .iter()
+            .map(|rows| box_table(rows))
+            .collect::<Vec<String>>();
+        if let Some(partner) = &self.aspect_partner {
+            lines.push(format!("aspect partner: {partner}"));
+        }
+        lines.join("\n")
+    }
+
+    pub fn to_html_tables(&self) -> String {
+        self.tables()
+            .iter()
+            .map(|rows| html_table(rows))
+            .collect::<Vec<String>>()
+            .join("")
+    }
+
+    // for one anki field per form, empty when the page didn't have it
+    pub fn nth_form(forms: &[VerbForm], n: usize) -> &str {
+        forms.get(n).map(|f| f.form.as_str()).unwrap_or("")
+    }
+}